@@ -1,18 +1,92 @@
 use hyper;
+use hyper::Url;
 use hyper::method::Method::{Get, Put};
 use hyper::header::ContentType;
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json;
 use ::JValue;
 
-use Consul;
-use error::consul_error;
+use {Consul, HttpResponse};
+#[cfg(any(test, feature = "async"))]
+use {AsyncConsul, ConsulFuture};
+use error::{consul_error, Error};
 
 use std::io::Read;
 use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+// CheckStatus is the state of a health check. Statuses are ordered by severity, from passing to maintenance,
+// followed by the ones this crate doesn't know.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CheckStatus {
+    Passing,
+    Warning,
+    Critical,
+    /// Consul doesn't set checks to this status, `ServiceEntry::aggregated_status` returns it
+    /// for nodes and services in maintenance mode
+    Maintenance,
+    /// A status sent by a newer Consul
+    Unknown(String),
+}
+
+impl CheckStatus {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            CheckStatus::Passing => "passing",
+            CheckStatus::Warning => "warning",
+            CheckStatus::Critical => "critical",
+            CheckStatus::Maintenance => "maintenance",
+            CheckStatus::Unknown(ref status) => status,
+        }
+    }
+
+    /// Returns the most severe of `statuses`, the way Consul aggregates the checks of a service.
+    /// No checks at all count as passing. An unknown status ranks worst, like in the Go client
+    /// which gives up on aggregating when it meets one, so it is never mistaken for healthy.
+    pub fn aggregate<'s, I>(statuses: I) -> CheckStatus where I: IntoIterator<Item = &'s CheckStatus> {
+        statuses.into_iter().cloned().max().unwrap_or(CheckStatus::Passing)
+    }
+
+    // Checks can only be set to, and queried by, the statuses Consul stores
+    pub(crate) fn _is_check_state(&self) -> bool {
+        matches!(*self, CheckStatus::Passing | CheckStatus::Warning | CheckStatus::Critical)
+    }
+}
+
+impl<'s> From<&'s str> for CheckStatus {
+    fn from(status: &'s str) -> Self {
+        match status {
+            "passing" => CheckStatus::Passing,
+            "warning" => CheckStatus::Warning,
+            "critical" => CheckStatus::Critical,
+            "maintenance" => CheckStatus::Maintenance,
+            status => CheckStatus::Unknown(status.to_string()),
+        }
+    }
+}
+
+impl Serialize for CheckStatus {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl Deserialize for CheckStatus {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer {
+        String::deserialize(deserializer).map(|status| CheckStatus::from(&status[..]))
+    }
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
 // AgentCheck represents a check known to the agent
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AgentCheck {
     #[serde(rename = "Node")]
     pub node: String,
@@ -21,7 +95,7 @@ pub struct AgentCheck {
     #[serde(rename = "Name")]
     pub name: String,
     #[serde(rename = "Status")]
-    pub status: String,
+    pub status: CheckStatus,
     #[serde(rename = "Notes")]
     pub notes: String,
     #[serde(rename = "Output")]
@@ -33,7 +107,7 @@ pub struct AgentCheck {
 }
 
 // AgentService represents a service known to the agent
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AgentService {
     #[serde(rename = "ID")]
     pub id: String,
@@ -49,6 +123,139 @@ pub struct AgentService {
     pub enable_tag_override: bool,
 }
 
+// MemberStatus is the gossip (Serf) status of a cluster member
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberStatus {
+    Alive,
+    Leaving,
+    Left,
+    Failed,
+    /// A status this crate does not know about, by its numeric value
+    Unknown(u64),
+}
+
+impl MemberStatus {
+    fn code(&self) -> u64 {
+        match *self {
+            MemberStatus::Alive => 1,
+            MemberStatus::Leaving => 2,
+            MemberStatus::Left => 3,
+            MemberStatus::Failed => 4,
+            MemberStatus::Unknown(code) => code,
+        }
+    }
+}
+
+impl From<u64> for MemberStatus {
+    fn from(code: u64) -> Self {
+        match code {
+            1 => MemberStatus::Alive,
+            2 => MemberStatus::Leaving,
+            3 => MemberStatus::Left,
+            4 => MemberStatus::Failed,
+            code => MemberStatus::Unknown(code),
+        }
+    }
+}
+
+// Consul sends the status as the number Serf uses internally
+impl Serialize for MemberStatus {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_u64(self.code())
+    }
+}
+
+impl Deserialize for MemberStatus {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer {
+        u64::deserialize(deserializer).map(MemberStatus::from)
+    }
+}
+
+// AgentMember represents a member of the LAN or WAN gossip pool as seen by the agent
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AgentMember {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Addr")]
+    pub addr: String,
+    #[serde(rename = "Port")]
+    pub port: u16,
+    /// Serf tags, e.g. `dc`, `role` (`consul` for servers), `build` or `segment`
+    #[serde(rename = "Tags")]
+    pub tags: HashMap<String, String>,
+    #[serde(rename = "Status")]
+    pub status: MemberStatus,
+    #[serde(rename = "ProtocolMin")]
+    pub protocol_min: u8,
+    #[serde(rename = "ProtocolMax")]
+    pub protocol_max: u8,
+    #[serde(rename = "ProtocolCur")]
+    pub protocol_cur: u8,
+    #[serde(rename = "DelegateMin")]
+    pub delegate_min: u8,
+    #[serde(rename = "DelegateMax")]
+    pub delegate_max: u8,
+    #[serde(rename = "DelegateCur")]
+    pub delegate_cur: u8,
+}
+
+// AgentConfig is the subset of the agent's configuration reported by `agent/self`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AgentConfig {
+    #[serde(rename = "Datacenter")]
+    pub datacenter: String,
+    #[serde(default, rename = "PrimaryDatacenter")]
+    pub primary_datacenter: String,
+    #[serde(rename = "NodeName")]
+    pub node_name: String,
+    #[serde(default, rename = "NodeID")]
+    pub node_id: String,
+    #[serde(rename = "Server")]
+    pub server: bool,
+    #[serde(rename = "Revision")]
+    pub revision: String,
+    #[serde(rename = "Version")]
+    pub version: String,
+}
+
+// Coordinate is the agent's position in the network tomography used to estimate round trip times
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Coordinate {
+    #[serde(rename = "Vec")]
+    pub vec: Vec<f64>,
+    #[serde(rename = "Error")]
+    pub error: f64,
+    #[serde(rename = "Adjustment")]
+    pub adjustment: f64,
+    #[serde(rename = "Height")]
+    pub height: f64,
+}
+
+// AgentSelf is the agent's description of itself
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AgentSelf {
+    #[serde(rename = "Config")]
+    pub config: AgentConfig,
+    /// The full runtime configuration, its layout changes between Consul versions
+    #[serde(default, rename = "DebugConfig")]
+    pub debug_config: JValue,
+    /// Absent if network coordinates are disabled
+    #[serde(default, rename = "Coord")]
+    pub coord: Option<Coordinate>,
+    #[serde(rename = "Member")]
+    pub member: AgentMember,
+    #[serde(default, rename = "Meta", deserialize_with = "_deserialize_or_default")]
+    pub meta: HashMap<String, String>,
+    /// Runtime statistics by subsystem (`agent`, `consul`, `raft`, `runtime`, ...)
+    #[serde(default, rename = "Stats", deserialize_with = "_deserialize_or_default")]
+    pub stats: HashMap<String, HashMap<String, String>>,
+}
+
+// Consul sends `null` for empty maps
+fn _deserialize_or_default<D, T>(deserializer: D) -> Result<T, D::Error> where D: Deserializer, T: Deserialize + Default {
+    Option::<T>::deserialize(deserializer).map(|v| v.unwrap_or_default())
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(deny_unknown_fields)]
 pub struct AgentCheckRegistration {
@@ -65,24 +272,29 @@ pub struct AgentCheckRegistration {
     pub docker_container_id: Option<String>,
     #[serde(rename = "Shell", skip_serializing_if = "Option::is_none")]
     pub shell: Option<String>,
-    #[serde(rename = "Interval", skip_serializing_if = "Option::is_none")]
-    pub interval: Option<String>,
-    #[serde(rename = "Timeout", skip_serializing_if = "Option::is_none")]
-    pub timeout: Option<String>,
-    #[serde(rename = "TTL", skip_serializing_if = "Option::is_none")]
-    pub ttl: Option<String>,
+    #[serde(default, rename = "Interval", skip_serializing_if = "Option::is_none",
+            serialize_with = "::duration::_serialize_option", deserialize_with = "::duration::_deserialize_option")]
+    pub interval: Option<Duration>,
+    #[serde(default, rename = "Timeout", skip_serializing_if = "Option::is_none",
+            serialize_with = "::duration::_serialize_option", deserialize_with = "::duration::_deserialize_option")]
+    pub timeout: Option<Duration>,
+    #[serde(default, rename = "TTL", skip_serializing_if = "Option::is_none",
+            serialize_with = "::duration::_serialize_option", deserialize_with = "::duration::_deserialize_option")]
+    pub ttl: Option<Duration>,
     #[serde(rename = "HTTP", skip_serializing_if = "Option::is_none")]
     pub http: Option<String>,
     #[serde(rename = "TCP", skip_serializing_if = "Option::is_none")]
     pub tcp: Option<String>,
+    /// Initial status of the check, Consul registers checks as critical by default
     #[serde(rename = "Status", skip_serializing_if = "Option::is_none")]
-    pub status: Option<String>,
+    pub status: Option<CheckStatus>,
     #[serde(rename = "Notes", skip_serializing_if = "Option::is_none")]
     pub notes: Option<String>,
     #[serde(rename = "TLSSkipVerify", skip_serializing_if = "Option::is_none")]
     pub tls_skip_verify: Option<bool>,
-    #[serde(rename = "DeregisterCriticalServiceAfter", skip_serializing_if = "Option::is_none")]
-    pub deregister_critical_service_after: Option<String>,
+    #[serde(default, rename = "DeregisterCriticalServiceAfter", skip_serializing_if = "Option::is_none",
+            serialize_with = "::duration::_serialize_option", deserialize_with = "::duration::_deserialize_option")]
+    pub deregister_critical_service_after: Option<Duration>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -105,15 +317,41 @@ pub struct AgentServiceRegistration {
     pub check: Option<AgentCheckRegistration>,
 }
 
+impl AgentCheckRegistration {
+    pub fn from_json(x: JValue) -> ::Result<Self> {
+        Ok(serde_json::from_value(x)?)
+    }
+}
+
+// Kept for one release so `.into()` callers still build. Rustc doesn't act on #[deprecated] for trait impls,
+// the doc comment is what users see.
+/// Deprecated, panics on invalid JSON: use `from_json`. Will be removed in the next release.
+#[allow(useless_deprecated)]
+#[deprecated(note = "panics on invalid JSON, use `AgentCheckRegistration::from_json`")]
 impl From<JValue> for AgentCheckRegistration {
     fn from(x: JValue) -> Self {
-        serde_json::from_value(x).unwrap()
+        match AgentCheckRegistration::from_json(x) {
+            Ok(x) => x,
+            Err(e) => panic!("Invalid check registration: {}", e),
+        }
     }
 }
 
+impl AgentServiceRegistration {
+    pub fn from_json(x: JValue) -> ::Result<Self> {
+        Ok(serde_json::from_value(x)?)
+    }
+}
+
+/// Deprecated, panics on invalid JSON: use `from_json`. Will be removed in the next release.
+#[allow(useless_deprecated)]
+#[deprecated(note = "panics on invalid JSON, use `AgentServiceRegistration::from_json`")]
 impl From<JValue> for AgentServiceRegistration {
     fn from(x: JValue) -> Self {
-        serde_json::from_value(x).unwrap()
+        match AgentServiceRegistration::from_json(x) {
+            Ok(x) => x,
+            Err(e) => panic!("Invalid service registration: {}", e),
+        }
     }
 }
 
@@ -121,31 +359,52 @@ pub struct Agent<'a> {
     consul: &'a Consul
 }
 
+fn _map_response<T>(mut res: HttpResponse) -> ::Result<HashMap<String, T>> where T: Deserialize {
+    match res.status {
+        hyper::Ok => {
+            let mut buf = String::new();
+            res.read_to_string(&mut buf)?;
+
+            Ok(serde_json::from_str(&buf)?)
+        },
+        _ => Err(consul_error(res)),
+    }
+}
+
+fn _members_query(url: &mut Url, wan: bool, segment: Option<&str>) {
+    if wan {
+        url.query_pairs_mut().append_pair("wan", "1");
+    }
+    if let Some(segment) = segment {
+        url.query_pairs_mut().append_pair("segment", segment);
+    }
+}
+
+fn _json_response<T>(mut res: HttpResponse) -> ::Result<T> where T: Deserialize {
+    match res.status {
+        hyper::Ok => {
+            let mut buf = String::new();
+            res.read_to_string(&mut buf)?;
+
+            Ok(serde_json::from_str(&buf)?)
+        },
+        _ => Err(consul_error(res)),
+    }
+}
+
 impl<'a> Agent<'a> {
     pub fn new(consul: &'a Consul) -> Self {
         Agent { consul: consul }
     }
 
     pub fn checks(&self) -> ::Result<HashMap<String, AgentCheck>> {
-        let mut res = self.consul._request1(Get, "agent/checks").send()?;
-        match res.status {
-            hyper::Ok => {
-                let mut buf = String::new();
-                res.read_to_string(&mut buf).expect("Cannot fill the buffer");
-
-                let v: JValue = serde_json::from_str(&buf).expect("Cannot parse JSON");
-                Ok(
-                    v.as_object().unwrap().iter().map(|(k, v)| (k.clone(), serde_json::from_value(v.clone()).unwrap()))
-                        .collect::<HashMap<_, _>>()
-                )
-            },
-            _ => Err(consul_error(res)),
-        }
+        let res = self.consul._request1(Get, "agent/checks").send()?;
+        _map_response(res)
     }
 
     pub fn register_check(&self, check: &AgentCheckRegistration) -> ::Result<()> {
         let res = self.consul._request1(Put, "agent/check/register")
-            .body(&serde_json::to_string(check).unwrap())
+            .body(serde_json::to_string(check)?)
             .header(ContentType::json())
             .send()?;
         match res.status {
@@ -173,6 +432,23 @@ impl<'a> Agent<'a> {
         }
     }
 
+    /// Sets the status and output of a TTL check in one call.
+    /// Consul only accepts passing, warning and critical here.
+    pub fn update_check(&self, check_id: &str, status: CheckStatus, output: Option<&str>) -> ::Result<()> {
+        if !status._is_check_state() {
+            return Err(Error::InvalidCheckStatus(status));
+        }
+        let body = json!({ "Status": status.as_str(), "Output": output.unwrap_or("") });
+        let res = self.consul._request2(Put, "agent/check/update", &[check_id])
+            .body(serde_json::to_string(&body)?)
+            .header(ContentType::json())
+            .send()?;
+        match res.status {
+            hyper::Ok => Ok(()),
+            _ => Err(consul_error(res)),
+        }
+    }
+
     pub fn pass_check(&self, check_id: &str, note: Option<&str>) -> ::Result<()> {
         self._set_check_status(check_id, "pass", note)
     }
@@ -186,25 +462,13 @@ impl<'a> Agent<'a> {
     }
 
     pub fn services(&self) -> ::Result<HashMap<String, AgentService>> {
-        let mut res = self.consul._request1(Get, "agent/services").send()?;
-        match res.status {
-            hyper::Ok => {
-                let mut buf = String::new();
-                res.read_to_string(&mut buf).expect("Cannot fill the buffer");
-
-                let v: JValue = serde_json::from_str(&buf).expect("Cannot parse JSON");
-                Ok(
-                    v.as_object().unwrap().iter().map(|(k, v)| (k.clone(), serde_json::from_value(v.clone()).unwrap()))
-                        .collect::<HashMap<_, _>>()
-                )
-            },
-            _ => Err(consul_error(res)),
-        }
+        let res = self.consul._request1(Get, "agent/services").send()?;
+        _map_response(res)
     }
 
     pub fn register_service(&self, service: &AgentServiceRegistration) -> ::Result<()> {
         let res = self.consul._request1(Put, "agent/service/register")
-            .body(&serde_json::to_string(service).unwrap())
+            .body(serde_json::to_string(service)?)
             .header(ContentType::json())
             .send()?;
         match res.status {
@@ -246,35 +510,29 @@ impl<'a> Agent<'a> {
         self.fail_check(&format!("service:{}", service_id), note)
     }
 
+    /// Lists the members of the LAN gossip pool, or of the WAN pool of servers if `wan` is set.
+    /// `segment` limits the LAN members to a network segment, `"_all"` lists the members of all segments.
+    pub fn members(&self, wan: bool, segment: Option<&str>) -> ::Result<Vec<AgentMember>> {
+        let res = self.consul._request3(Get, "agent", &["members"], |u| _members_query(u, wan, segment)).send()?;
+        _json_response(res)
+    }
+
     pub fn members_j(&self, wan: bool) -> ::Result<JValue> {
-        let mut res = self.consul._request3(
-            Get, "agent", &["members"],
-            |u| if wan { u.query_pairs_mut().append_pair("wan", "1"); }
+        let res = self.consul._request3(
+            Get, "agent", &["members"], |u| _members_query(u, wan, None)
         ).send()?;
-        match res.status {
-            hyper::Ok => {
-                let mut buf = String::new();
-                res.read_to_string(&mut buf).expect("Cannot fill the buffer");
-
-                let v: JValue = serde_json::from_str(&buf).expect("Cannot parse JSON");
-                Ok(v)
-            },
-            _ => Err(consul_error(res)),
-        }
+        _json_response(res)
     }
 
     pub fn self_j(&self) -> ::Result<JValue> {
-        let mut res = self.consul._request1(Get, "agent/self").send()?;
-        match res.status {
-            hyper::Ok => {
-                let mut buf = String::new();
-                res.read_to_string(&mut buf).expect("Cannot fill the buffer");
+        let res = self.consul._request1(Get, "agent/self").send()?;
+        _json_response(res)
+    }
 
-                let v: JValue = serde_json::from_str(&buf).expect("Cannot parse JSON");
-                Ok(v)
-            },
-            _ => Err(consul_error(res)),
-        }
+    /// Returns the agent's configuration, member record, metadata and statistics
+    pub fn self_info(&self) -> ::Result<AgentSelf> {
+        let res = self.consul._request1(Get, "agent/self").send()?;
+        _json_response(res)
     }
 
     pub fn reload(&self) -> ::Result<()> {
@@ -326,38 +584,224 @@ impl<'a> Agent<'a> {
     }
 }
 
+// AsyncAgent is the async counterpart of `Agent`
+#[cfg(any(test, feature = "async"))]
+pub struct AsyncAgent<'a> {
+    consul: &'a AsyncConsul
+}
+
+#[cfg(any(test, feature = "async"))]
+impl<'a> AsyncAgent<'a> {
+    pub fn new(consul: &'a AsyncConsul) -> Self {
+        AsyncAgent { consul: consul }
+    }
+
+    pub fn checks(&self) -> ConsulFuture<HashMap<String, AgentCheck>> {
+        self.consul._send(self.consul.consul()._request1(Get, "agent/checks"), _map_response)
+    }
+
+    pub fn register_check(&self, check: &AgentCheckRegistration) -> ConsulFuture<()> {
+        let body = match serde_json::to_string(check) {
+            Ok(body) => body,
+            Err(err) => return ::nonblocking::_ready(Err(err.into())),
+        };
+        let req = self.consul.consul()._request1(Put, "agent/check/register")
+            .body(body)
+            .header(ContentType::json());
+        self.consul._send(req, ::_empty_response)
+    }
+
+    pub fn deregister_check(&self, check_id: &str) -> ConsulFuture<()> {
+        self.consul._send(self.consul.consul()._request2(Get, "agent/check/deregister", &[check_id]), ::_empty_response)
+    }
+
+    fn _set_check_status(&self, check_id: &str, status: &str, note: Option<&str>) -> ConsulFuture<()> {
+        let req = self.consul.consul()._request3(
+            Get, "agent/check", &[status, check_id],
+            |u| if let Some(note) = note { u.query_pairs_mut().append_pair("note", note); }
+        );
+        self.consul._send(req, ::_empty_response)
+    }
+
+    /// See `Agent::update_check`
+    pub fn update_check(&self, check_id: &str, status: CheckStatus, output: Option<&str>) -> ConsulFuture<()> {
+        if !status._is_check_state() {
+            return ::nonblocking::_ready(Err(Error::InvalidCheckStatus(status)));
+        }
+        let body = json!({ "Status": status.as_str(), "Output": output.unwrap_or("") });
+        let body = match serde_json::to_string(&body) {
+            Ok(body) => body,
+            Err(err) => return ::nonblocking::_ready(Err(err.into())),
+        };
+        let req = self.consul.consul()._request2(Put, "agent/check/update", &[check_id])
+            .body(body)
+            .header(ContentType::json());
+        self.consul._send(req, ::_empty_response)
+    }
+
+    pub fn pass_check(&self, check_id: &str, note: Option<&str>) -> ConsulFuture<()> {
+        self._set_check_status(check_id, "pass", note)
+    }
+
+    pub fn warn_check(&self, check_id: &str, note: Option<&str>) -> ConsulFuture<()> {
+        self._set_check_status(check_id, "warn", note)
+    }
+
+    pub fn fail_check(&self, check_id: &str, note: Option<&str>) -> ConsulFuture<()> {
+        self._set_check_status(check_id, "fail", note)
+    }
+
+    pub fn services(&self) -> ConsulFuture<HashMap<String, AgentService>> {
+        self.consul._send(self.consul.consul()._request1(Get, "agent/services"), _map_response)
+    }
+
+    pub fn register_service(&self, service: &AgentServiceRegistration) -> ConsulFuture<()> {
+        let body = match serde_json::to_string(service) {
+            Ok(body) => body,
+            Err(err) => return ::nonblocking::_ready(Err(err.into())),
+        };
+        let req = self.consul.consul()._request1(Put, "agent/service/register")
+            .body(body)
+            .header(ContentType::json());
+        self.consul._send(req, ::_empty_response)
+    }
+
+    pub fn deregister_service(&self, service_id: &str) -> ConsulFuture<()> {
+        self.consul._send(self.consul.consul()._request2(Get, "agent/service/deregister", &[service_id]), ::_empty_response)
+    }
+
+    pub fn maintenance_service(&self, service_id: &str, enable: bool, reason: Option<&str>) -> ConsulFuture<()> {
+        let req = self.consul.consul()._request3(
+            Put, "agent/service/maintenance", &[service_id],
+            |u| if let Some(r) = reason {
+                u.query_pairs_mut().append_pair("reason", r).append_pair("enable", if enable { "true" } else { "false" });
+            }
+        );
+        self.consul._send(req, ::_empty_response)
+    }
+
+    pub fn pass_service_check(&self, service_id: &str, note: Option<&str>) -> ConsulFuture<()> {
+        self.pass_check(&format!("service:{}", service_id), note)
+    }
+
+    pub fn warn_service_check(&self, service_id: &str, note: Option<&str>) -> ConsulFuture<()> {
+        self.warn_check(&format!("service:{}", service_id), note)
+    }
+
+    pub fn fail_service_check(&self, service_id: &str, note: Option<&str>) -> ConsulFuture<()> {
+        self.fail_check(&format!("service:{}", service_id), note)
+    }
+
+    /// See `Agent::members`
+    pub fn members(&self, wan: bool, segment: Option<&str>) -> ConsulFuture<Vec<AgentMember>> {
+        let req = self.consul.consul()._request3(Get, "agent", &["members"], |u| _members_query(u, wan, segment));
+        self.consul._send(req, _json_response)
+    }
+
+    pub fn members_j(&self, wan: bool) -> ConsulFuture<JValue> {
+        let req = self.consul.consul()._request3(
+            Get, "agent", &["members"], |u| _members_query(u, wan, None)
+        );
+        self.consul._send(req, _json_response)
+    }
+
+    pub fn self_j(&self) -> ConsulFuture<JValue> {
+        self.consul._send(self.consul.consul()._request1(Get, "agent/self"), _json_response)
+    }
+
+    pub fn self_info(&self) -> ConsulFuture<AgentSelf> {
+        self.consul._send(self.consul.consul()._request1(Get, "agent/self"), _json_response)
+    }
+
+    pub fn reload(&self) -> ConsulFuture<()> {
+        self.consul._send(self.consul.consul()._request1(Put, "agent/reload"), ::_empty_response)
+    }
+
+    pub fn maintenance(&self, enable: bool, reason: Option<&str>) -> ConsulFuture<()> {
+        let req = self.consul.consul()._request3(
+            Put, "agent", &["maintenance"],
+            |u| if let Some(r) = reason {
+                u.query_pairs_mut().append_pair("reason", r).append_pair("enable", if enable { "true" } else { "false" });
+            }
+        );
+        self.consul._send(req, ::_empty_response)
+    }
+
+    pub fn join(&self, address: &str, wan: bool) -> ConsulFuture<()> {
+        let req = self.consul.consul()._request3(
+            Put, "agent/join", &[address],
+            |u| if wan { u.query_pairs_mut().append_pair("wan", "1"); }
+        );
+        self.consul._send(req, ::_empty_response)
+    }
+
+    pub fn leave(&self) -> ConsulFuture<()> {
+        self.consul._send(self.consul.consul()._request1(Put, "agent/leave"), ::_empty_response)
+    }
+
+    pub fn force_leave(&self, node: &str) -> ConsulFuture<()> {
+        self.consul._send(self.consul.consul()._request2(Put, "agent/force-leave", &[node]), ::_empty_response)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use ::{Consul, AgentCheckRegistration, AgentServiceRegistration};
+    use serde_json;
+    use ::{AgentCheckRegistration, AgentMember, AgentSelf, AgentServiceRegistration, CheckStatus, FakeConsul, MemberStatus};
+    use std::time::Duration;
 
     #[test]
     fn checks() {
-        let consul = Consul::default();
+        let consul = FakeConsul::new().consul();
 
         assert!(consul.agent().register_check(&AgentCheckRegistration {
             name: "test_check".into(),
-            ttl: Some("15s".into()),
-            status: Some("critical".into()),
+            ttl: Some(Duration::from_secs(15)),
+            status: Some(CheckStatus::Critical),
             .. Default::default()
         }).is_ok());
         assert!(consul.agent().checks().unwrap().contains_key("test_check"));
 
         assert!(consul.agent().pass_check("test_check", None).is_ok());
-        assert!(consul.agent().checks().unwrap()["test_check"].status.as_str() == "passing");
+        assert_eq!(consul.agent().checks().unwrap()["test_check"].status, CheckStatus::Passing);
 
         assert!(consul.agent().warn_check("test_check", None).is_ok());
-        assert!(consul.agent().checks().unwrap()["test_check"].status.as_str() == "warning");
+        assert_eq!(consul.agent().checks().unwrap()["test_check"].status, CheckStatus::Warning);
 
         assert!(consul.agent().fail_check("test_check", None).is_ok());
-        assert!(consul.agent().checks().unwrap()["test_check"].status.as_str() == "critical");
+        assert_eq!(consul.agent().checks().unwrap()["test_check"].status, CheckStatus::Critical);
 
         assert!(consul.agent().deregister_check("test_check").is_ok());
         assert!(consul.agent().checks().unwrap().contains_key("test_check") == false);
     }
 
+    #[test]
+    fn check_durations() {
+        let check = AgentCheckRegistration {
+            name: "test_check".into(),
+            http: Some("http://127.0.0.1:8080/health".into()),
+            interval: Some(Duration::from_secs(10)),
+            timeout: Some(Duration::from_millis(500)),
+            deregister_critical_service_after: Some(Duration::from_secs(90)),
+            .. Default::default()
+        };
+        let v = serde_json::to_value(&check).unwrap();
+        assert_eq!(v["Interval"].as_str(), Some("10s"));
+        assert_eq!(v["Timeout"].as_str(), Some("500ms"));
+        assert_eq!(v["DeregisterCriticalServiceAfter"].as_str(), Some("1m30s"));
+        assert!(v.get("TTL").is_none());
+
+        let check = AgentCheckRegistration::from_json(json!({ "Name": "test_check", "TTL": "1m" })).unwrap();
+        assert_eq!(check.ttl, Some(Duration::from_secs(60)));
+        assert!(AgentCheckRegistration::from_json(json!({ "Name": "test_check", "TTL": "1 minute" })).is_err());
+
+        let check: AgentCheckRegistration = json!({ "Name": "test_check", "TTL": "1m" }).into();
+        assert_eq!(check.ttl, Some(Duration::from_secs(60)));
+    }
+
     #[test]
     fn services() {
-        let consul = Consul::default();
+        let consul = FakeConsul::new().consul();
 
         assert!(consul.agent().register_service(&AgentServiceRegistration {
             name: "test_service".into(),
@@ -373,12 +817,56 @@ mod tests {
         assert!(consul.agent().services().ok().unwrap().contains_key("test_service") == false);
     }
 
+    #[test]
+    fn check_status() {
+        assert_eq!(CheckStatus::aggregate(&[]), CheckStatus::Passing);
+        assert_eq!(CheckStatus::aggregate(&[CheckStatus::Passing, CheckStatus::Critical, CheckStatus::Warning]), CheckStatus::Critical);
+        assert_eq!(CheckStatus::aggregate(&[CheckStatus::Maintenance, CheckStatus::Critical]), CheckStatus::Maintenance);
+        let unknown = CheckStatus::Unknown("degraded".into());
+        assert_eq!(CheckStatus::aggregate(&[CheckStatus::Critical, unknown.clone(), CheckStatus::Maintenance]), unknown);
+
+        assert_eq!(serde_json::to_string(&CheckStatus::Warning).unwrap(), r#""warning""#);
+        assert_eq!(serde_json::from_str::<CheckStatus>(r#""critical""#).unwrap(), CheckStatus::Critical);
+        assert_eq!(serde_json::from_str::<CheckStatus>(r#""passnig""#).unwrap(), CheckStatus::Unknown("passnig".into()));
+        assert_eq!(serde_json::to_string(&CheckStatus::Unknown("degraded".into())).unwrap(), r#""degraded""#);
+        assert!(serde_json::from_str::<CheckStatus>("1").is_err());
+    }
+
+    #[test]
+    fn members() {
+        let consul = FakeConsul::new().consul();
+
+        let members = consul.agent().members(false, None).unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].status, MemberStatus::Alive);
+        assert_eq!(members[0].tags["dc"], "dc1");
+        assert!(members[0].protocol_cur >= members[0].protocol_min && members[0].protocol_cur <= members[0].protocol_max);
+
+        let member: AgentMember = serde_json::from_str(r#"{"Name": "node1", "Addr": "10.0.0.1", "Port": 8301, "Tags": {},
+            "Status": 9, "ProtocolMin": 1, "ProtocolMax": 5, "ProtocolCur": 2, "DelegateMin": 2, "DelegateMax": 5, "DelegateCur": 4}"#).unwrap();
+        assert_eq!(member.status, MemberStatus::Unknown(9));
+    }
+
     #[test]
     fn _self() {
-        let consul = Consul::default();
+        let consul = FakeConsul::new().consul();
 
         let conf = consul.agent().self_j();
         assert!(conf.is_ok());
         assert!(conf.unwrap().as_object().unwrap().contains_key("Config"));
+
+        let info = consul.agent().self_info().unwrap();
+        assert_eq!(info.config.datacenter, "dc1");
+        assert!(info.config.server);
+        assert_eq!(info.member.name, info.config.node_name);
+        assert_eq!(info.stats["consul"]["leader"], "true");
+
+        let info: AgentSelf = serde_json::from_value(json!({
+            "Config": { "Datacenter": "dc2", "NodeName": "old", "Server": false, "Revision": "", "Version": "0.9.0" },
+            "Coord": null,
+            "Member": serde_json::to_value(&info.member).unwrap(),
+            "Meta": null,
+        })).unwrap();
+        assert!(info.coord.is_none() && info.meta.is_empty() && info.stats.is_empty() && info.debug_config.is_null());
     }
 }
@@ -0,0 +1,235 @@
+use hyper;
+use hyper::Url;
+use hyper::method::Method::{Get, Put};
+use hyper::header::ContentType;
+
+use serde::Deserialize;
+use serde_json;
+
+use {AgentCheck, AgentService, Consul};
+use error::consul_error;
+
+use std::io::Read;
+use std::collections::HashMap;
+
+// CatalogNode represents a node registered in the catalog
+#[derive(Deserialize, Debug, Clone)]
+pub struct CatalogNode {
+    #[serde(default, rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Node")]
+    pub node: String,
+    #[serde(rename = "Address")]
+    pub address: String,
+    #[serde(default, rename = "Datacenter")]
+    pub datacenter: String,
+    #[serde(default, rename = "TaggedAddresses")]
+    pub tagged_addresses: Option<HashMap<String, String>>,
+    #[serde(default, rename = "Meta")]
+    pub meta: Option<HashMap<String, String>>,
+    #[serde(default, rename = "CreateIndex")]
+    pub create_index: u64,
+    #[serde(default, rename = "ModifyIndex")]
+    pub modify_index: u64,
+}
+
+// CatalogService represents an instance of a service on a node
+#[derive(Deserialize, Debug, Clone)]
+pub struct CatalogService {
+    #[serde(default, rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Node")]
+    pub node: String,
+    #[serde(rename = "Address")]
+    pub address: String,
+    #[serde(default, rename = "Datacenter")]
+    pub datacenter: String,
+    #[serde(default, rename = "TaggedAddresses")]
+    pub tagged_addresses: Option<HashMap<String, String>>,
+    #[serde(default, rename = "NodeMeta")]
+    pub node_meta: Option<HashMap<String, String>>,
+    #[serde(rename = "ServiceID")]
+    pub service_id: String,
+    #[serde(rename = "ServiceName")]
+    pub service_name: String,
+    #[serde(rename = "ServiceAddress")]
+    pub service_address: String,
+    #[serde(default, rename = "ServiceTags")]
+    pub service_tags: Option<Vec<String>>,
+    #[serde(rename = "ServicePort")]
+    pub service_port: u32,
+    #[serde(default, rename = "ServiceEnableTagOverride")]
+    pub service_enable_tag_override: bool,
+    #[serde(default, rename = "CreateIndex")]
+    pub create_index: u64,
+    #[serde(default, rename = "ModifyIndex")]
+    pub modify_index: u64,
+}
+
+// CatalogNodeServices holds a node with all the services registered on it
+#[derive(Deserialize, Debug, Clone)]
+pub struct CatalogNodeServices {
+    #[serde(rename = "Node")]
+    pub node: CatalogNode,
+    #[serde(default, rename = "Services")]
+    pub services: Option<HashMap<String, AgentService>>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CatalogRegistration {
+    #[serde(rename = "ID", skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "Node")]
+    pub node: String,
+    #[serde(rename = "Address")]
+    pub address: String,
+    #[serde(rename = "Datacenter", skip_serializing_if = "Option::is_none")]
+    pub datacenter: Option<String>,
+    #[serde(rename = "TaggedAddresses", skip_serializing_if = "Option::is_none")]
+    pub tagged_addresses: Option<HashMap<String, String>>,
+    #[serde(rename = "NodeMeta", skip_serializing_if = "Option::is_none")]
+    pub node_meta: Option<HashMap<String, String>>,
+    #[serde(rename = "Service", skip_serializing_if = "Option::is_none")]
+    pub service: Option<AgentService>,
+    #[serde(rename = "Check", skip_serializing_if = "Option::is_none")]
+    pub check: Option<AgentCheck>,
+}
+
+/// Removes a whole node from the catalog, or only one of its services or checks if `service_id` or `check_id` is set
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CatalogDeregistration {
+    #[serde(rename = "Node")]
+    pub node: String,
+    #[serde(rename = "Datacenter", skip_serializing_if = "Option::is_none")]
+    pub datacenter: Option<String>,
+    #[serde(rename = "ServiceID", skip_serializing_if = "Option::is_none")]
+    pub service_id: Option<String>,
+    #[serde(rename = "CheckID", skip_serializing_if = "Option::is_none")]
+    pub check_id: Option<String>,
+}
+
+pub struct Catalog<'a> {
+    consul: &'a Consul
+}
+
+impl<'a> Catalog<'a> {
+    pub fn new(consul: &'a Consul) -> Self {
+        Catalog { consul: consul }
+    }
+
+    pub fn datacenters(&self) -> ::Result<Vec<String>> {
+        self._get("catalog/datacenters", &[], |_| ())
+    }
+
+    /// Lists all nodes, sorted by round trip time from the `near` node if given (`_agent` means the local agent)
+    pub fn nodes(&self, near: Option<&str>) -> ::Result<Vec<CatalogNode>> {
+        self._get("catalog/nodes", &[], |u| if let Some(near) = near { u.query_pairs_mut().append_pair("near", near); })
+    }
+
+    /// Returns the names of all services with their tags
+    pub fn services(&self) -> ::Result<HashMap<String, Vec<String>>> {
+        self._get("catalog/services", &[], |_| ())
+    }
+
+    /// Lists the instances of a service, optionally only those with `tag`, sorted by round trip time from `near`
+    pub fn service(&self, service: &str, tag: Option<&str>, near: Option<&str>) -> ::Result<Vec<CatalogService>> {
+        self._get("catalog/service", &[service], |u| {
+            if let Some(tag) = tag { u.query_pairs_mut().append_pair("tag", tag); }
+            if let Some(near) = near { u.query_pairs_mut().append_pair("near", near); }
+        })
+    }
+
+    /// Returns the node with its services, or `None` if the node is unknown
+    pub fn node(&self, node: &str) -> ::Result<Option<CatalogNodeServices>> {
+        self._get("catalog/node", &[node], |_| ())
+    }
+
+    pub fn register(&self, registration: &CatalogRegistration) -> ::Result<()> {
+        let res = self.consul._request1(Put, "catalog/register")
+            .body(serde_json::to_string(registration)?)
+            .header(ContentType::json())
+            .send()?;
+        match res.status {
+            hyper::Ok => Ok(()),
+            _ => Err(consul_error(res)),
+        }
+    }
+
+    pub fn deregister(&self, deregistration: &CatalogDeregistration) -> ::Result<()> {
+        let res = self.consul._request1(Put, "catalog/deregister")
+            .body(serde_json::to_string(deregistration)?)
+            .header(ContentType::json())
+            .send()?;
+        match res.status {
+            hyper::Ok => Ok(()),
+            _ => Err(consul_error(res)),
+        }
+    }
+
+    fn _get<T, F>(&self, srv: &str, segments: &[&str], url_f: F) -> ::Result<T> where T: Deserialize, F: Fn(&mut Url) {
+        let mut res = self.consul._request3(Get, srv, segments, url_f).send()?;
+        match res.status {
+            hyper::Ok => {
+                let mut buf = String::new();
+                res.read_to_string(&mut buf)?;
+
+                Ok(serde_json::from_str(&buf)?)
+            },
+            _ => Err(consul_error(res)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{AgentService, CatalogDeregistration, CatalogRegistration, FakeConsul};
+
+    #[test]
+    fn datacenters_and_nodes() {
+        let consul = FakeConsul::new().consul();
+
+        let datacenters = consul.catalog().datacenters().unwrap();
+        assert!(!datacenters.is_empty());
+
+        let nodes = consul.catalog().nodes(Some("_agent")).unwrap();
+        assert!(!nodes.is_empty());
+        assert!(consul.catalog().node(&nodes[0].node).unwrap().is_some());
+        assert!(consul.catalog().node("test_no_such_node").unwrap().is_none());
+    }
+
+    #[test]
+    fn register() {
+        let consul = FakeConsul::new().consul();
+
+        assert!(consul.catalog().register(&CatalogRegistration {
+            node: "test_catalog_node".into(),
+            address: "192.0.2.10".into(),
+            service: Some(AgentService {
+                id: "test_catalog_service1".into(),
+                service: "test_catalog_service".into(),
+                tags: vec!["primary".into()],
+                port: 8000,
+                address: "".into(),
+                enable_tag_override: false,
+            }),
+            .. Default::default()
+        }).is_ok());
+
+        assert!(consul.catalog().services().unwrap()["test_catalog_service"] == vec!["primary".to_string()]);
+
+        let instances = consul.catalog().service("test_catalog_service", Some("primary"), None).unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].node, "test_catalog_node");
+        assert_eq!(instances[0].service_port, 8000);
+        assert!(consul.catalog().service("test_catalog_service", Some("secondary"), None).unwrap().is_empty());
+
+        let node = consul.catalog().node("test_catalog_node").unwrap().unwrap();
+        assert!(node.services.unwrap().contains_key("test_catalog_service1"));
+
+        assert!(consul.catalog().deregister(&CatalogDeregistration {
+            node: "test_catalog_node".into(),
+            .. Default::default()
+        }).is_ok());
+        assert!(consul.catalog().node("test_catalog_node").unwrap().is_none());
+    }
+}
@@ -0,0 +1,157 @@
+use serde::{Deserialize, Deserializer, Serializer};
+use serde::de::Error as DeError;
+
+use error::Error;
+
+use std::time::Duration;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Parses a Go duration as used throughout Consul's API and configuration,
+/// a sequence of decimal numbers with a unit: `"15s"`, `"1m30s"`, `"1.5h"`, `"500ms"`.
+/// Valid units are `ns`, `us` (or `µs`), `ms`, `s`, `m` and `h`. Negative durations are rejected.
+pub fn parse_duration(s: &str) -> ::Result<Duration> {
+    let invalid = || Error::InvalidDuration(s.to_string());
+
+    let mut rest = s.strip_prefix('+').unwrap_or(s);
+    // A unit is only optional for zero
+    if rest == "0" {
+        return Ok(Duration::new(0, 0));
+    }
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total: u128 = 0;
+    while !rest.is_empty() {
+        let (int, r) = rest.split_at(rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len()));
+        let (frac, r) = match r.strip_prefix('.') {
+            Some(r) => r.split_at(r.find(|c: char| !c.is_ascii_digit()).unwrap_or(r.len())),
+            None => ("", r),
+        };
+        if int.is_empty() && frac.is_empty() {
+            return Err(invalid());
+        }
+        let (unit, r) = r.split_at(r.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(r.len()));
+        let unit: u128 = match unit {
+            "ns" => 1,
+            "us" | "µs" | "μs" => 1_000,
+            "ms" => 1_000_000,
+            "s" => NANOS_PER_SEC,
+            "m" => 60 * NANOS_PER_SEC,
+            "h" => 3600 * NANOS_PER_SEC,
+            _ => return Err(invalid()),
+        };
+
+        let int: u128 = if int.is_empty() { 0 } else { int.parse().map_err(|_| invalid())? };
+        total = int.checked_mul(unit).and_then(|v| total.checked_add(v)).ok_or_else(invalid)?;
+        // Digits beyond nanosecond precision don't matter, even in hours
+        let frac = &frac[..frac.len().min(15)];
+        if !frac.is_empty() {
+            let scale = 10u128.pow(frac.len() as u32);
+            let frac = frac.parse::<u128>().map_err(|_| invalid())? * unit / scale;
+            total = total.checked_add(frac).ok_or_else(invalid)?;
+        }
+        rest = r;
+    }
+
+    if total / NANOS_PER_SEC > u64::MAX as u128 {
+        return Err(invalid());
+    }
+    Ok(Duration::new((total / NANOS_PER_SEC) as u64, (total % NANOS_PER_SEC) as u32))
+}
+
+/// Formats a duration the way Go does, which Consul accepts back: `"15s"`, `"1m30s"`, `"1h0m0s"`, `"500ms"`
+pub fn format_duration(d: Duration) -> String {
+    let nanos = d.as_secs() as u128 * NANOS_PER_SEC + d.subsec_nanos() as u128;
+    if nanos == 0 {
+        "0s".to_string()
+    } else if nanos < 1_000 {
+        format!("{}ns", nanos)
+    } else if nanos < 1_000_000 {
+        format!("{}µs", _decimal(nanos, 1_000))
+    } else if nanos < NANOS_PER_SEC {
+        format!("{}ms", _decimal(nanos, 1_000_000))
+    } else {
+        let (hours, minutes) = (d.as_secs() / 3600, d.as_secs() / 60 % 60);
+        let seconds = _decimal(nanos % (60 * NANOS_PER_SEC), NANOS_PER_SEC);
+        if hours > 0 {
+            format!("{}h{}m{}s", hours, minutes, seconds)
+        } else if minutes > 0 {
+            format!("{}m{}s", minutes, seconds)
+        } else {
+            format!("{}s", seconds)
+        }
+    }
+}
+
+// `value / unit` with the fraction's trailing zeros dropped
+fn _decimal(value: u128, unit: u128) -> String {
+    let (int, frac) = (value / unit, value % unit);
+    if frac == 0 {
+        return int.to_string();
+    }
+    let width = unit.to_string().len() - 1;
+    format!("{}.{}", int, format!("{:0width$}", frac, width = width).trim_end_matches('0'))
+}
+
+/// Serializes an optional duration field as a Go duration, use with `skip_serializing_if = "Option::is_none"`
+pub fn _serialize_option<S>(value: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+    match *value {
+        Some(d) => serializer.serialize_str(&format_duration(d)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Deserializes an optional duration field from a Go duration, Consul sends unset ones as `""`
+pub fn _deserialize_option<D>(deserializer: D) -> Result<Option<Duration>, D::Error> where D: Deserializer {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(ref s) if s.is_empty() => Ok(None),
+        Some(s) => parse_duration(&s).map(Some).map_err(|e| D::Error::custom(e.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Deserializes a duration Consul sends as a number of nanoseconds, such as a session's lock delay
+pub fn _deserialize_nanos<D>(deserializer: D) -> Result<Duration, D::Error> where D: Deserializer {
+    u64::deserialize(deserializer).map(|nanos| Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{format_duration, parse_duration};
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_duration("0").unwrap(), Duration::new(0, 0));
+        assert_eq!(parse_duration("15s").unwrap(), Duration::from_secs(15));
+        assert_eq!(parse_duration("1m30s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("1.5h").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration("+2h45m0.5s").unwrap(), Duration::from_millis(9_900_500));
+        assert_eq!(parse_duration(".5us").unwrap(), Duration::new(0, 500));
+        assert_eq!(parse_duration("1µs1ns").unwrap(), Duration::new(0, 1001));
+
+        for s in &["", "15", "s", "-1s", "1.s.", "1d", "1 s", "15s ", "99999999999999999999h"] {
+            assert!(parse_duration(s).is_err(), "{:?} should not parse", s);
+        }
+        let overflow = format!("{}h.999999999999999h", u128::MAX / 3_600_000_000_000);
+        assert!(parse_duration(&overflow).is_err());
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(format_duration(Duration::new(0, 0)), "0s");
+        assert_eq!(format_duration(Duration::new(0, 42)), "42ns");
+        assert_eq!(format_duration(Duration::new(0, 1500)), "1.5µs");
+        assert_eq!(format_duration(Duration::from_millis(500)), "500ms");
+        assert_eq!(format_duration(Duration::from_secs(15)), "15s");
+        assert_eq!(format_duration(Duration::from_millis(90_250)), "1m30.25s");
+        assert_eq!(format_duration(Duration::from_secs(3600)), "1h0m0s");
+
+        for d in &[Duration::new(0, 1), Duration::new(1, 1), Duration::from_millis(1234), Duration::from_secs(86_400 * 365)] {
+            assert_eq!(parse_duration(&format_duration(*d)).unwrap(), *d);
+        }
+    }
+}
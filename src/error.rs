@@ -1,12 +1,15 @@
 use std::fmt;
-use std::io::Read;
+use std::io::{self, Read};
 use std::error::Error as StdError;
 use std::str::Utf8Error;
 use std::string::FromUtf8Error;
 
 use hyper;
+use native_tls;
+use serde_json;
 use hyper::error::ParseError;
-use hyper::client::response::Response;
+use hyper::status::StatusCode;
+use {CheckStatus, HttpResponse};
 
 /// Result type often returned from methods that can have `Error`s.
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -16,7 +19,50 @@ pub enum Error {
     Http(hyper::Error),
     Uri(ParseError),
     Utf8(Utf8Error),
-    Consul(String),
+    Tls(native_tls::Error),
+    /// Reading the response body failed
+    Io(io::Error),
+    /// The response body is not the JSON Consul is expected to send
+    Json(serde_json::Error),
+    /// Consul rejected the request as invalid (400)
+    BadRequest(StatusCode, String),
+    /// The ACL token is missing or lacks the required permission (401, 403)
+    PermissionDenied(StatusCode, String),
+    /// The endpoint or object does not exist (404)
+    NotFound(StatusCode, String),
+    /// The agent is rate limiting requests (429)
+    RateLimited(StatusCode, String),
+    /// Consul or a proxy failed to serve the request (5xx)
+    ServerError(StatusCode, String),
+    /// Any other unexpected status, with the response body
+    Status(StatusCode, String),
+    /// A string is not a valid Go duration, see `parse_duration`
+    InvalidDuration(String),
+    /// A semaphore can't have a limit of zero
+    ZeroSemaphoreLimit,
+    /// The semaphore is already held with another limit: (its limit, the requested one)
+    SemaphoreLimitConflict(usize, usize),
+    /// The contender key of a new semaphore session is locked already
+    SemaphoreContenderLocked(String),
+    /// Checks can't be set to or queried by this status, only by passing, warning and critical
+    InvalidCheckStatus(CheckStatus),
+    Config(String),
+}
+
+impl Error {
+    /// Returns the HTTP status of errors returned by Consul
+    pub fn status(&self) -> Option<StatusCode> {
+        match *self {
+            Error::BadRequest(status, _) | Error::PermissionDenied(status, _) | Error::NotFound(status, _) |
+            Error::RateLimited(status, _) | Error::ServerError(status, _) | Error::Status(status, _) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// Whether the request may succeed if sent again later: connection failures, rate limiting and server errors
+    pub fn is_retryable(&self) -> bool {
+        matches!(*self, Error::Http(_) | Error::Io(_) | Error::RateLimited(..) | Error::ServerError(..))
+    }
 }
 
 impl fmt::Display for Error {
@@ -25,7 +71,21 @@ impl fmt::Display for Error {
             Error::Http(ref err) => write!(f, "Http error: {}", err),
             Error::Uri(ref err) => write!(f, "Uri parse error: {}", err),
             Error::Utf8(ref err) => write!(f, "UTF8 error: {}", err),
-            Error::Consul(ref err) => write!(f, "Consul response: {}", err),
+            Error::Tls(ref err) => write!(f, "TLS error: {}", err),
+            Error::Io(ref err) => write!(f, "IO error: {}", err),
+            Error::Json(ref err) => write!(f, "JSON error: {}", err),
+            Error::BadRequest(status, ref body) => write!(f, "Bad request ({}): {}", status, body),
+            Error::PermissionDenied(status, ref body) => write!(f, "Permission denied ({}): {}", status, body),
+            Error::NotFound(status, ref body) => write!(f, "Not found ({}): {}", status, body),
+            Error::RateLimited(status, ref body) => write!(f, "Rate limited ({}): {}", status, body),
+            Error::ServerError(status, ref body) => write!(f, "Server error ({}): {}", status, body),
+            Error::Status(status, ref body) => write!(f, "Unexpected response ({}): {}", status, body),
+            Error::InvalidDuration(ref s) => write!(f, "Invalid duration: {:?}", s),
+            Error::ZeroSemaphoreLimit => write!(f, "Semaphore limit must be positive"),
+            Error::SemaphoreLimitConflict(held, requested) => write!(f, "Semaphore limit conflict: held with {}, requested {}", held, requested),
+            Error::SemaphoreContenderLocked(ref key) => write!(f, "Semaphore contender key is locked already: {}", key),
+            Error::InvalidCheckStatus(ref status) => write!(f, "Invalid check status: {}", status),
+            Error::Config(ref err) => write!(f, "Configuration error: {}", err),
         }
     }
 }
@@ -36,7 +96,17 @@ impl StdError for Error {
             Error::Http(ref err) => err.description(),
             Error::Uri(ref err) => err.description(),
             Error::Utf8(ref err) => err.description(),
-            Error::Consul(ref err) => err,
+            Error::Tls(ref err) => err.description(),
+            Error::Io(ref err) => err.description(),
+            Error::Json(ref err) => err.description(),
+            Error::BadRequest(_, ref body) | Error::PermissionDenied(_, ref body) | Error::NotFound(_, ref body) |
+            Error::RateLimited(_, ref body) | Error::ServerError(_, ref body) | Error::Status(_, ref body) => body,
+            Error::InvalidDuration(ref s) => s,
+            Error::ZeroSemaphoreLimit => "semaphore limit must be positive",
+            Error::SemaphoreLimitConflict(..) => "semaphore limit conflict",
+            Error::SemaphoreContenderLocked(ref key) => key,
+            Error::InvalidCheckStatus(ref status) => status.as_str(),
+            Error::Config(ref err) => err,
         }
     }
 
@@ -45,6 +115,9 @@ impl StdError for Error {
             Error::Http(ref err) => Some(err),
             Error::Uri(ref err) => Some(err),
             Error::Utf8(ref err) => Some(err),
+            Error::Tls(ref err) => Some(err),
+            Error::Io(ref err) => Some(err),
+            Error::Json(ref err) => Some(err),
             _ => None,
         }
     }
@@ -68,15 +141,41 @@ impl From<Utf8Error> for Error {
     }
 }
 
+impl From<native_tls::Error> for Error {
+    fn from(err: native_tls::Error) -> Error {
+        Error::Tls(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::Json(err)
+    }
+}
+
 impl From<FromUtf8Error> for Error {
     fn from(err: FromUtf8Error) -> Error {
         Error::Utf8(err.utf8_error())
     }
 }
 
-pub fn consul_error(mut resp: Response) -> Error {
-    assert!(resp.status != hyper::Ok);
-    let mut buf = String::new();
-    resp.read_to_string(&mut buf).unwrap();
-    Error::Consul(format!("{}: {}", resp.status.canonical_reason().unwrap(), buf))
+pub fn consul_error(mut resp: HttpResponse) -> Error {
+    // The body is only informative, a proxy may well send something that isn't UTF-8
+    let mut buf = Vec::new();
+    let _ = resp.read_to_end(&mut buf);
+    let body = String::from_utf8_lossy(&buf).trim().to_string();
+    match resp.status {
+        StatusCode::BadRequest => Error::BadRequest(resp.status, body),
+        StatusCode::Unauthorized | StatusCode::Forbidden => Error::PermissionDenied(resp.status, body),
+        StatusCode::NotFound => Error::NotFound(resp.status, body),
+        StatusCode::TooManyRequests => Error::RateLimited(resp.status, body),
+        status if status.is_server_error() => Error::ServerError(status, body),
+        status => Error::Status(status, body),
+    }
 }
@@ -0,0 +1,1079 @@
+use base64;
+use hyper::header::Headers;
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use serde_json;
+use url::percent_encoding::percent_decode;
+use ::JValue;
+
+use {Consul, ConsulBuilder, HttpRequest, HttpResponse, Transport};
+use duration::{format_duration, parse_duration};
+use error::Error;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+const NODE: &str = "fake-node";
+const NODE_ADDRESS: &str = "127.0.0.1";
+const DATACENTER: &str = "dc1";
+const DEFAULT_WAIT: u64 = 5 * 60 * 1000;
+const MAX_WAIT: u64 = 10 * 60 * 1000;
+const DEFAULT_LOCK_DELAY: u64 = 15 * 1000;
+
+// FakeConsul is an in-process stand-in for a Consul agent, used as a `Transport`.
+// It serves the KV (including transactions and blocking queries), agent service/check,
+// session, catalog and health endpoints of a single-node datacenter.
+// Indexes follow Consul's: every write gets the next index, and blocking queries wait for it.
+// TTL checks never expire on their own, sessions with a TTL do.
+#[derive(Clone)]
+pub struct FakeConsul {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Clone)]
+struct KvEntry {
+    create_index: u64,
+    modify_index: u64,
+    lock_index: u64,
+    flags: u64,
+    value: Vec<u8>,
+    session: Option<String>,
+}
+
+struct FakeSession {
+    create_index: u64,
+    name: String,
+    node: String,
+    checks: Vec<String>,
+    lock_delay: Duration,
+    behavior: String,
+    ttl: Option<Duration>,
+    expires: Option<Instant>,
+}
+
+#[derive(Clone)]
+struct Service {
+    id: String,
+    name: String,
+    tags: Vec<String>,
+    address: String,
+    port: u64,
+    enable_tag_override: bool,
+    create_index: u64,
+    modify_index: u64,
+}
+
+#[derive(Clone)]
+struct Check {
+    id: String,
+    name: String,
+    status: String,
+    notes: String,
+    output: String,
+    service_id: String,
+    service_name: String,
+    create_index: u64,
+    modify_index: u64,
+}
+
+struct Node {
+    address: String,
+    tagged_addresses: JValue,
+    meta: JValue,
+    services: BTreeMap<String, Service>,
+    checks: BTreeMap<String, Check>,
+    create_index: u64,
+    modify_index: u64,
+}
+
+impl Node {
+    fn new(address: &str, index: u64) -> Self {
+        Node {
+            address: address.to_string(),
+            tagged_addresses: json!({ "lan": address, "wan": address }),
+            meta: json!({}),
+            services: BTreeMap::new(),
+            checks: BTreeMap::new(),
+            create_index: index,
+            modify_index: index,
+        }
+    }
+}
+
+struct Reply {
+    status: StatusCode,
+    body: Vec<u8>,
+    index: u64,
+}
+
+impl Reply {
+    fn json(index: u64, value: JValue) -> Reply {
+        Reply { status: StatusCode::Ok, body: serde_json::to_vec(&value).unwrap(), index: index }
+    }
+
+    fn error(index: u64, status: StatusCode, msg: &str) -> Reply {
+        Reply { status: status, body: msg.as_bytes().to_vec(), index: index }
+    }
+
+    fn not_found(index: u64) -> Reply {
+        Reply::error(index, StatusCode::NotFound, "")
+    }
+}
+
+type Query = HashMap<String, String>;
+
+struct State {
+    index: u64,
+    kv_index: u64,
+    kv: BTreeMap<String, KvEntry>,
+    tombstones: BTreeMap<String, u64>,
+    lock_delays: HashMap<String, Instant>,
+    sessions: BTreeMap<String, FakeSession>,
+    next_session: u64,
+    nodes: BTreeMap<String, Node>,
+}
+
+impl FakeConsul {
+    pub fn new() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(NODE.to_string(), Node::new(NODE_ADDRESS, 1));
+
+        let state = State {
+            index: 1,
+            kv_index: 1,
+            kv: BTreeMap::new(),
+            tombstones: BTreeMap::new(),
+            lock_delays: HashMap::new(),
+            sessions: BTreeMap::new(),
+            next_session: 0,
+            nodes: nodes,
+        };
+        FakeConsul { inner: Arc::new(Inner { state: Mutex::new(state), changed: Condvar::new() }) }
+    }
+
+    /// Returns a client talking to this fake
+    pub fn consul(&self) -> Consul {
+        ConsulBuilder::new().transport(self.clone()).build().unwrap()
+    }
+}
+
+impl Default for FakeConsul {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for FakeConsul {
+    fn send(&self, request: HttpRequest) -> ::Result<HttpResponse> {
+        let path = request.url.path_segments()
+            .map(|segments| segments.skip(1).map(|s| percent_decode(s.as_bytes()).decode_utf8_lossy().into_owned()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let query = request.url.query_pairs().into_owned().collect::<Query>();
+        let body = request.body.unwrap_or_default();
+
+        let wait_index = query.get("index").and_then(|v| v.parse().ok()).unwrap_or(0);
+        let wait = query.get("wait").and_then(|v| parse_duration(v).ok()).unwrap_or(Duration::from_millis(DEFAULT_WAIT));
+        let deadline = Instant::now() + ::std::cmp::min(wait, Duration::from_millis(MAX_WAIT));
+
+        let mut state = self.inner.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let index = state.index;
+            state.expire_sessions();
+            let path = path.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+            let reply = state.handle(&request.method, &path, &query, &body);
+            if state.index != index {
+                self.inner.changed.notify_all();
+            }
+
+            let now = Instant::now();
+            if request.method != Method::Get || wait_index == 0 || reply.index > wait_index || now >= deadline {
+                let mut headers = Headers::new();
+                headers.set_raw("X-Consul-Index", vec![reply.index.to_string().into_bytes()]);
+                headers.set_raw("X-Consul-KnownLeader", vec![b"true".to_vec()]);
+                headers.set_raw("X-Consul-LastContact", vec![b"0".to_vec()]);
+                return Ok(HttpResponse::new(reply.status, headers, reply.body));
+            }
+
+            // Wake up at least every second to expire sessions
+            let timeout = ::std::cmp::min(deadline - now, Duration::from_secs(1));
+            state = self.inner.changed.wait_timeout(state, timeout).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+}
+
+// Partition cuts clients off from a FakeConsul to test how code behaves during a network partition:
+// while it's down, every request sent through it fails as if it timed out
+#[derive(Clone)]
+pub struct Partition {
+    fake: FakeConsul,
+    down: Arc<AtomicBool>,
+}
+
+impl Partition {
+    pub fn new(fake: &FakeConsul) -> Self {
+        Partition { fake: fake.clone(), down: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// Returns a client whose requests go through this partition
+    pub fn consul(&self) -> Consul {
+        ConsulBuilder::new().transport(self.clone()).build().unwrap()
+    }
+
+    pub fn set_down(&self, down: bool) {
+        self.down.store(down, Ordering::SeqCst);
+    }
+}
+
+impl Transport for Partition {
+    fn send(&self, request: HttpRequest) -> ::Result<HttpResponse> {
+        if self.down.load(Ordering::SeqCst) {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::TimedOut, "partitioned")));
+        }
+        self.fake.send(request)
+    }
+}
+
+fn _nanos(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64
+}
+
+fn _str(v: &JValue, key: &str) -> String {
+    v[key].as_str().unwrap_or("").to_string()
+}
+
+fn _strings(v: &JValue) -> Vec<String> {
+    v.as_array().map(|a| a.iter().filter_map(|s| s.as_str().map(|s| s.to_string())).collect()).unwrap_or_default()
+}
+
+fn _kv_json(key: &str, entry: &KvEntry, with_value: bool) -> JValue {
+    let mut v = json!({
+        "CreateIndex": entry.create_index,
+        "ModifyIndex": entry.modify_index,
+        "LockIndex": entry.lock_index,
+        "Key": key,
+        "Flags": entry.flags,
+        "Value": JValue::Null,
+    });
+    if with_value && !entry.value.is_empty() {
+        v["Value"] = JValue::String(base64::encode(&entry.value));
+    }
+    if let Some(ref session) = entry.session {
+        v["Session"] = JValue::String(session.clone());
+    }
+    v
+}
+
+fn _service_json(service: &Service) -> JValue {
+    json!({
+        "ID": service.id,
+        "Service": service.name,
+        "Tags": service.tags,
+        "Address": service.address,
+        "Port": service.port,
+        "EnableTagOverride": service.enable_tag_override,
+        "CreateIndex": service.create_index,
+        "ModifyIndex": service.modify_index,
+    })
+}
+
+fn _check_json(node: &str, check: &Check) -> JValue {
+    json!({
+        "Node": node,
+        "CheckID": check.id,
+        "Name": check.name,
+        "Status": check.status,
+        "Notes": check.notes,
+        "Output": check.output,
+        "ServiceID": check.service_id,
+        "ServiceName": check.service_name,
+        "CreateIndex": check.create_index,
+        "ModifyIndex": check.modify_index,
+    })
+}
+
+fn _node_json(name: &str, node: &Node) -> JValue {
+    json!({
+        "ID": "",
+        "Node": name,
+        "Address": node.address,
+        "Datacenter": DATACENTER,
+        "TaggedAddresses": node.tagged_addresses,
+        "Meta": node.meta,
+        "CreateIndex": node.create_index,
+        "ModifyIndex": node.modify_index,
+    })
+}
+
+fn _member_json() -> JValue {
+    json!({
+        "Name": NODE,
+        "Addr": NODE_ADDRESS,
+        "Port": 8301,
+        "Tags": { "dc": DATACENTER, "role": "consul", "port": "8300", "build": "1.0.0:fake", "vsn": "2", "vsn_min": "2", "vsn_max": "3" },
+        "Status": 1,
+        "ProtocolMin": 1,
+        "ProtocolMax": 5,
+        "ProtocolCur": 2,
+        "DelegateMin": 2,
+        "DelegateMax": 5,
+        "DelegateCur": 4,
+    })
+}
+
+impl State {
+    fn handle(&mut self, method: &Method, path: &[&str], query: &Query, body: &[u8]) -> Reply {
+        if path.is_empty() {
+            return Reply::not_found(self.index);
+        }
+        let (srv, rest) = (path[0], &path[1..]);
+        match srv {
+            "kv" => {
+                let key = rest.join("/");
+                match *method {
+                    Method::Get => self.kv_get(&key, query),
+                    Method::Put => self.kv_put(&key, query, body),
+                    Method::Delete => self.kv_delete(&key, query),
+                    _ => Reply::error(self.index, StatusCode::MethodNotAllowed, "method not allowed"),
+                }
+            },
+            "txn" => self.txn(body),
+            "session" => self.session(rest, body),
+            "agent" => self.agent(rest, query, body),
+            "catalog" => self.catalog(rest, query, body),
+            "health" => self.health(rest, query),
+            _ => Reply::not_found(self.index),
+        }
+    }
+
+    // KV
+
+    fn kv_prefix_index(&self, prefix: &str) -> u64 {
+        let entries = self.kv.iter().filter(|&(k, _)| k.starts_with(prefix)).map(|(_, e)| e.modify_index);
+        let tombstones = self.tombstones.iter().filter(|&(k, _)| k.starts_with(prefix)).map(|(_, &i)| i);
+        match entries.chain(tombstones).max() {
+            Some(index) => index,
+            None => self.kv_index,
+        }
+    }
+
+    fn kv_get(&self, key: &str, query: &Query) -> Reply {
+        if query.contains_key("keys") {
+            let separator = query.get("separator").map(|s| s.as_str()).unwrap_or("");
+            let mut keys = BTreeSet::new();
+            for k in self.kv.keys().filter(|k| k.starts_with(key)) {
+                match k[key.len()..].find(separator) {
+                    Some(pos) if !separator.is_empty() => keys.insert(k[..key.len() + pos + separator.len()].to_string()),
+                    _ => keys.insert(k.clone()),
+                };
+            }
+            let index = self.kv_prefix_index(key);
+            if keys.is_empty() {
+                return Reply::not_found(index);
+            }
+            Reply::json(index, json!(keys.into_iter().collect::<Vec<_>>()))
+        } else if query.contains_key("recurse") {
+            let entries = self.kv.iter()
+                .filter(|&(k, _)| k.starts_with(key))
+                .map(|(k, e)| _kv_json(k, e, true))
+                .collect::<Vec<_>>();
+            let index = self.kv_prefix_index(key);
+            if entries.is_empty() {
+                return Reply::not_found(index);
+            }
+            Reply::json(index, JValue::Array(entries))
+        } else {
+            let tombstone = self.tombstones.get(key).cloned().unwrap_or(0);
+            match self.kv.get(key) {
+                Some(entry) => Reply::json(entry.modify_index, json!([_kv_json(key, entry, true)])),
+                None => Reply::not_found(if tombstone > 0 { tombstone } else { self.kv_index }),
+            }
+        }
+    }
+
+    fn kv_set(&mut self, index: u64, key: &str, value: Vec<u8>, flags: u64, session: Option<String>, lock: bool) -> KvEntry {
+        let entry = self.kv.entry(key.to_string()).or_insert(KvEntry {
+            create_index: index,
+            modify_index: index,
+            lock_index: 0,
+            flags: 0,
+            value: Vec::new(),
+            session: None,
+        });
+        entry.modify_index = index;
+        entry.value = value;
+        entry.flags = flags;
+        entry.session = session;
+        if lock {
+            entry.lock_index += 1;
+        }
+        self.tombstones.remove(key);
+        entry.clone()
+    }
+
+    fn kv_remove(&mut self, index: u64, key: &str) {
+        if self.kv.remove(key).is_some() {
+            self.tombstones.insert(key.to_string(), index);
+        }
+    }
+
+    fn kv_can_acquire(&self, key: &str, session: &str) -> bool {
+        match self.kv.get(key).and_then(|e| e.session.as_ref()) {
+            Some(holder) => holder == session,
+            None => match self.lock_delays.get(key) {
+                Some(&until) => Instant::now() >= until,
+                None => true,
+            },
+        }
+    }
+
+    fn kv_put(&mut self, key: &str, query: &Query, body: &[u8]) -> Reply {
+        let index = self.index + 1;
+        let flags = query.get("flags").and_then(|v| v.parse().ok()).unwrap_or(0);
+
+        if let Some(cas) = query.get("cas") {
+            let cas: u64 = match cas.parse() {
+                Ok(cas) => cas,
+                Err(_) => return Reply::error(self.index, StatusCode::BadRequest, "Invalid cas index"),
+            };
+            let matches = match self.kv.get(key) {
+                Some(entry) => entry.modify_index == cas,
+                None => cas == 0,
+            };
+            if !matches {
+                return Reply::json(self.index, json!(false));
+            }
+        }
+
+        let current = self.kv.get(key).and_then(|e| e.session.clone());
+        if let Some(session) = query.get("acquire") {
+            if !self.sessions.contains_key(session) {
+                return Reply::error(self.index, StatusCode::InternalServerError, "invalid session");
+            }
+            if !self.kv_can_acquire(key, session) {
+                return Reply::json(self.index, json!(false));
+            }
+            let lock = current.is_none();
+            self.kv_set(index, key, body.to_vec(), flags, Some(session.clone()), lock);
+        } else if let Some(session) = query.get("release") {
+            if current.as_ref() != Some(session) {
+                return Reply::json(self.index, json!(false));
+            }
+            self.kv_set(index, key, body.to_vec(), flags, None, false);
+        } else {
+            self.kv_set(index, key, body.to_vec(), flags, current, false);
+        }
+
+        self.index = index;
+        self.kv_index = index;
+        Reply::json(index, json!(true))
+    }
+
+    fn kv_delete(&mut self, key: &str, query: &Query) -> Reply {
+        let index = self.index + 1;
+        if query.contains_key("recurse") {
+            let keys = self.kv.keys().filter(|k| k.starts_with(key)).cloned().collect::<Vec<_>>();
+            for k in keys {
+                self.kv_remove(index, &k);
+            }
+        } else if let Some(cas) = query.get("cas") {
+            let cas: u64 = cas.parse().unwrap_or(u64::MAX);
+            let matches = match self.kv.get(key) {
+                Some(entry) => entry.modify_index == cas,
+                None => cas == 0,
+            };
+            if !matches {
+                return Reply::json(self.index, json!(false));
+            }
+            self.kv_remove(index, key);
+        } else {
+            self.kv_remove(index, key);
+        }
+
+        self.index = index;
+        self.kv_index = index;
+        Reply::json(index, json!(true))
+    }
+
+    fn txn(&mut self, body: &[u8]) -> Reply {
+        let ops: JValue = match serde_json::from_slice(body) {
+            Ok(ops) => ops,
+            Err(err) => return Reply::error(self.index, StatusCode::BadRequest, &format!("Failed to parse body: {}", err)),
+        };
+        let ops = ops.as_array().cloned().unwrap_or_default();
+
+        let (kv, tombstones, lock_delays) = (self.kv.clone(), self.tombstones.clone(), self.lock_delays.clone());
+        let index = self.index + 1;
+        let mut results = Vec::new();
+        let mut written = false;
+
+        for (i, op) in ops.iter().enumerate() {
+            match self.txn_op(index, &op["KV"], &mut results, &mut written) {
+                Ok(()) => {},
+                Err(what) => {
+                    self.kv = kv;
+                    self.tombstones = tombstones;
+                    self.lock_delays = lock_delays;
+                    let mut reply = Reply::json(self.index, json!({
+                        "Results": JValue::Null,
+                        "Errors": [{ "OpIndex": i, "What": what }],
+                    }));
+                    reply.status = StatusCode::Conflict;
+                    return reply;
+                },
+            }
+        }
+
+        if written {
+            self.index = index;
+            self.kv_index = index;
+        }
+        Reply::json(self.index, json!({ "Results": results, "Errors": JValue::Null }))
+    }
+
+    fn txn_op(&mut self, index: u64, op: &JValue, results: &mut Vec<JValue>, written: &mut bool) -> ::std::result::Result<(), String> {
+        let verb = _str(op, "Verb");
+        let key = _str(op, "Key");
+        let value = match op["Value"].as_str() {
+            Some(v) => base64::decode(v).map_err(|e| format!("failed to decode value: {}", e))?,
+            None => Vec::new(),
+        };
+        let flags = op["Flags"].as_u64().unwrap_or(0);
+        let op_index = op["Index"].as_u64().unwrap_or(0);
+        let session = _str(op, "Session");
+        let modify_index = self.kv.get(&key).map(|e| e.modify_index);
+        let current = self.kv.get(&key).and_then(|e| e.session.clone());
+
+        let push = |results: &mut Vec<JValue>, entry: &KvEntry, with_value: bool| {
+            results.push(json!({ "KV": _kv_json(&key, entry, with_value) }));
+        };
+
+        match verb.as_str() {
+            "set" => {
+                let entry = self.kv_set(index, &key, value, flags, current, false);
+                push(results, &entry, false);
+            },
+            "cas" => {
+                if modify_index.unwrap_or(0) != op_index {
+                    return Err(format!("failed to set key {:?}, index is stale", key));
+                }
+                let entry = self.kv_set(index, &key, value, flags, current, false);
+                push(results, &entry, false);
+            },
+            "lock" => {
+                if !self.sessions.contains_key(&session) || !self.kv_can_acquire(&key, &session) {
+                    return Err(format!("failed to lock key {:?}, lock is already held", key));
+                }
+                let lock = current.is_none();
+                let entry = self.kv_set(index, &key, value, flags, Some(session), lock);
+                push(results, &entry, false);
+            },
+            "unlock" => {
+                if current.as_ref() != Some(&session) {
+                    return Err(format!("failed to unlock key {:?}, lock isn't held, or is held by another session", key));
+                }
+                let entry = self.kv_set(index, &key, value, flags, None, false);
+                push(results, &entry, false);
+            },
+            "get" => match self.kv.get(&key) {
+                Some(entry) => push(results, entry, true),
+                None => return Err(format!("key {:?} doesn't exist", key)),
+            },
+            "get-tree" => {
+                for (k, entry) in self.kv.iter().filter(|&(k, _)| k.starts_with(&key)) {
+                    results.push(json!({ "KV": _kv_json(k, entry, true) }));
+                }
+            },
+            "check-index" => match self.kv.get(&key) {
+                Some(entry) if entry.modify_index == op_index => push(results, entry, false),
+                _ => return Err(format!("current modify index for key {:?} doesn't match {}", key, op_index)),
+            },
+            "check-session" => match self.kv.get(&key) {
+                Some(entry) if entry.session.as_ref() == Some(&session) => push(results, entry, false),
+                _ => return Err(format!("lock for key {:?} isn't held by session {:?}", key, session)),
+            },
+            "delete" => self.kv_remove(index, &key),
+            "delete-tree" => {
+                let keys = self.kv.keys().filter(|k| k.starts_with(&key)).cloned().collect::<Vec<_>>();
+                for k in keys {
+                    self.kv_remove(index, &k);
+                }
+            },
+            "delete-cas" => {
+                if modify_index.unwrap_or(0) != op_index {
+                    return Err(format!("failed to delete key {:?}, index is stale", key));
+                }
+                self.kv_remove(index, &key);
+            },
+            _ => return Err(format!("unknown KV verb {:?}", verb)),
+        }
+
+        match verb.as_str() {
+            "set" | "cas" | "lock" | "unlock" | "delete" | "delete-tree" | "delete-cas" => *written = true,
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // Sessions
+
+    fn session_json(&self, id: &str, session: &FakeSession) -> JValue {
+        json!({
+            "CreateIndex": session.create_index,
+            "ID": id,
+            "Name": session.name,
+            "Node": session.node,
+            "Checks": session.checks,
+            "LockDelay": _nanos(session.lock_delay),
+            "Behavior": session.behavior,
+            "TTL": session.ttl.map(format_duration).unwrap_or_default(),
+        })
+    }
+
+    fn sessions_json<'a, I>(&self, sessions: I) -> JValue where I: Iterator<Item = (&'a String, &'a FakeSession)> {
+        JValue::Array(sessions.map(|(id, s)| self.session_json(id, s)).collect())
+    }
+
+    fn invalidate_session(&mut self, index: u64, id: &str) {
+        let session = match self.sessions.remove(id) {
+            Some(session) => session,
+            None => return,
+        };
+        let held = self.kv.iter()
+            .filter(|&(_, e)| e.session.as_deref() == Some(id))
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        for key in held {
+            if session.behavior == "delete" {
+                self.kv_remove(index, &key);
+            } else if let Some(entry) = self.kv.get_mut(&key) {
+                entry.session = None;
+                entry.modify_index = index;
+            }
+            self.lock_delays.insert(key, Instant::now() + session.lock_delay);
+        }
+        self.kv_index = index;
+    }
+
+    fn expire_sessions(&mut self) {
+        let now = Instant::now();
+        let expired = self.sessions.iter()
+            .filter(|&(_, s)| s.expires.is_some_and(|e| now >= e))
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for id in expired {
+            let index = self.index + 1;
+            self.invalidate_session(index, &id);
+            self.index = index;
+        }
+    }
+
+    fn session(&mut self, path: &[&str], body: &[u8]) -> Reply {
+        match path {
+            ["create"] => {
+                let req: JValue = serde_json::from_slice(body).unwrap_or(JValue::Null);
+                let index = self.index + 1;
+                self.next_session += 1;
+                let id = format!("00000000-0000-4000-8000-{:012x}", self.next_session);
+                let ttl = req["TTL"].as_str().and_then(|s| parse_duration(s).ok());
+                let node = match req["Node"].as_str() {
+                    Some(node) => node.to_string(),
+                    None => NODE.to_string(),
+                };
+                if !self.nodes.contains_key(&node) {
+                    return Reply::error(self.index, StatusCode::InternalServerError, "Missing node registration");
+                }
+                let checks = match req["Checks"] {
+                    JValue::Array(_) => _strings(&req["Checks"]),
+                    _ => vec!["serfHealth".to_string()],
+                };
+                self.sessions.insert(id.clone(), FakeSession {
+                    create_index: index,
+                    name: _str(&req, "Name"),
+                    node: node,
+                    checks: checks,
+                    lock_delay: req["LockDelay"].as_str().and_then(|s| parse_duration(s).ok())
+                        .unwrap_or(Duration::from_millis(DEFAULT_LOCK_DELAY)),
+                    behavior: req["Behavior"].as_str().unwrap_or("release").to_string(),
+                    ttl: ttl,
+                    expires: ttl.map(|ttl| Instant::now() + ttl),
+                });
+                self.index = index;
+                Reply::json(index, json!({ "ID": id }))
+            },
+            ["destroy", id] => {
+                let index = self.index + 1;
+                self.invalidate_session(index, id);
+                self.index = index;
+                Reply::json(index, json!(true))
+            },
+            ["renew", id] => {
+                let index = self.index;
+                match self.sessions.get_mut(*id) {
+                    Some(session) => session.expires = session.ttl.map(|ttl| Instant::now() + ttl),
+                    None => return Reply::error(index, StatusCode::NotFound, &format!("Session id '{}' not found", id)),
+                }
+                let json = self.session_json(id, &self.sessions[*id]);
+                Reply::json(index, json!([json]))
+            },
+            ["info", id] => match self.sessions.get(*id) {
+                Some(session) => Reply::json(self.index, json!([self.session_json(id, session)])),
+                None => Reply::json(self.index, JValue::Null),
+            },
+            ["list"] => Reply::json(self.index, self.sessions_json(self.sessions.iter())),
+            ["node", node] => Reply::json(self.index, self.sessions_json(self.sessions.iter().filter(|&(_, s)| s.node == *node))),
+            _ => Reply::not_found(self.index),
+        }
+    }
+
+    // Agent
+
+    fn local(&mut self) -> &mut Node {
+        self.nodes.entry(NODE.to_string()).or_insert_with(|| Node::new(NODE_ADDRESS, 1))
+    }
+
+    fn register_check(&mut self, index: u64, node: &str, check: &JValue, service_id: &str) -> ::std::result::Result<(), String> {
+        let name = _str(check, "Name");
+        let id = match check["CheckID"].as_str().or_else(|| check["ID"].as_str()) {
+            Some(id) => id.to_string(),
+            None if !service_id.is_empty() => format!("service:{}", service_id),
+            None => name.clone(),
+        };
+        if id.is_empty() {
+            return Err("Missing check name".to_string());
+        }
+        let service_id = check["ServiceID"].as_str().unwrap_or(service_id).to_string();
+        let node = self.nodes.get_mut(node).ok_or_else(|| "Unknown node".to_string())?;
+        let service_name = match node.services.get(&service_id) {
+            Some(service) => service.name.clone(),
+            None if service_id.is_empty() => String::new(),
+            None => return Err(format!("ServiceID {:?} does not exist", service_id)),
+        };
+        let create_index = node.checks.get(&id).map_or(index, |c| c.create_index);
+        node.checks.insert(id.clone(), Check {
+            id: id,
+            name: if name.is_empty() { format!("Service '{}' check", service_name) } else { name },
+            status: check["Status"].as_str().unwrap_or("critical").to_string(),
+            notes: _str(check, "Notes"),
+            output: _str(check, "Output"),
+            service_id: service_id,
+            service_name: service_name,
+            create_index: create_index,
+            modify_index: index,
+        });
+        Ok(())
+    }
+
+    fn register_service(&mut self, index: u64, node: &str, service: &JValue) -> ::std::result::Result<String, String> {
+        let name = match service["Name"].as_str().or_else(|| service["Service"].as_str()) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => return Err("Missing service name".to_string()),
+        };
+        let id = service["ID"].as_str().map(|s| s.to_string()).unwrap_or_else(|| name.clone());
+        let node = self.nodes.get_mut(node).ok_or_else(|| "Unknown node".to_string())?;
+        let create_index = node.services.get(&id).map_or(index, |s| s.create_index);
+        node.services.insert(id.clone(), Service {
+            id: id.clone(),
+            name: name,
+            tags: _strings(&service["Tags"]),
+            address: _str(service, "Address"),
+            port: service["Port"].as_u64().unwrap_or(0),
+            enable_tag_override: service["EnableTagOverride"].as_bool().unwrap_or(false),
+            create_index: create_index,
+            modify_index: index,
+        });
+        node.modify_index = index;
+        Ok(id)
+    }
+
+    fn agent(&mut self, path: &[&str], query: &Query, body: &[u8]) -> Reply {
+        let index = self.index + 1;
+        let req: JValue = serde_json::from_slice(body).unwrap_or(JValue::Null);
+        let res = match path {
+            ["checks"] => {
+                let checks = self.local().checks.iter()
+                    .map(|(id, c)| (id.clone(), _check_json(NODE, c)))
+                    .collect::<serde_json::Map<_, _>>();
+                return Reply::json(self.index, JValue::Object(checks));
+            },
+            ["services"] => {
+                let services = self.local().services.iter()
+                    .map(|(id, s)| (id.clone(), _service_json(s)))
+                    .collect::<serde_json::Map<_, _>>();
+                return Reply::json(self.index, JValue::Object(services));
+            },
+            ["self"] => {
+                let (checks, services) = (self.local().checks.len(), self.local().services.len());
+                return Reply::json(self.index, json!({
+                    "Config": {
+                        "Datacenter": DATACENTER,
+                        "NodeName": NODE,
+                        "NodeID": "00000000-0000-4000-8000-000000000000",
+                        "Server": true,
+                        "Revision": "fake",
+                        "Version": "1.0.0",
+                    },
+                    "DebugConfig": {},
+                    "Coord": { "Vec": [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], "Error": 1.5, "Adjustment": 0.0, "Height": 0.00001 },
+                    "Member": _member_json(),
+                    "Meta": {},
+                    "Stats": {
+                        "agent": { "checks": checks.to_string(), "services": services.to_string() },
+                        "consul": { "server": "true", "leader": "true", "known_datacenters": "1" },
+                    },
+                }));
+            },
+            ["members"] => return Reply::json(self.index, json!([_member_json()])),
+            ["check", "register"] => self.register_check(index, NODE, &req, ""),
+            ["check", "deregister", id] => {
+                self.local().checks.remove(*id);
+                Ok(())
+            },
+            ["check", status, id] if ["pass", "warn", "fail", "update"].contains(status) => {
+                let (status, output) = match *status {
+                    "pass" => ("passing".to_string(), query.get("note").cloned().unwrap_or_default()),
+                    "warn" => ("warning".to_string(), query.get("note").cloned().unwrap_or_default()),
+                    "fail" => ("critical".to_string(), query.get("note").cloned().unwrap_or_default()),
+                    _ => (_str(&req, "Status"), _str(&req, "Output")),
+                };
+                match self.local().checks.get_mut(*id) {
+                    Some(check) => {
+                        check.status = status;
+                        check.output = output;
+                        check.modify_index = index;
+                        Ok(())
+                    },
+                    None => Err(format!("Unknown check {:?}", id)),
+                }
+            },
+            ["service", "register"] => self.register_service(index, NODE, &req).and_then(|id| {
+                if !req["Check"].is_null() {
+                    self.register_check(index, NODE, &req["Check"], &id)?;
+                }
+                for (i, check) in req["Checks"].as_array().cloned().unwrap_or_default().iter().enumerate() {
+                    let mut check = check.clone();
+                    if check["CheckID"].is_null() {
+                        check["CheckID"] = JValue::String(format!("service:{}:{}", id, i + 1));
+                    }
+                    self.register_check(index, NODE, &check, &id)?;
+                }
+                Ok(())
+            }),
+            ["service", "deregister", id] => {
+                let node = self.local();
+                node.services.remove(*id);
+                node.checks = node.checks.iter().filter(|&(_, c)| c.service_id != *id).map(|(k, c)| (k.clone(), c.clone())).collect();
+                Ok(())
+            },
+            _ => return Reply::not_found(self.index),
+        };
+
+        match res {
+            Ok(()) => {
+                self.index = index;
+                Reply::json(index, JValue::Null)
+            },
+            Err(err) => Reply::error(self.index, StatusCode::InternalServerError, &err),
+        }
+    }
+
+    // Catalog
+
+    fn catalog(&mut self, path: &[&str], query: &Query, body: &[u8]) -> Reply {
+        let index = self.index;
+        match path {
+            ["datacenters"] => Reply::json(index, json!([DATACENTER])),
+            ["nodes"] => Reply::json(index, JValue::Array(self.nodes.iter().map(|(n, node)| _node_json(n, node)).collect())),
+            ["services"] => {
+                let mut services = BTreeMap::new();
+                for node in self.nodes.values() {
+                    for service in node.services.values() {
+                        services.entry(service.name.clone()).or_insert_with(BTreeSet::new).extend(service.tags.iter().cloned());
+                    }
+                }
+                let services = services.into_iter().map(|(k, tags)| (k, json!(tags.into_iter().collect::<Vec<_>>()))).collect();
+                Reply::json(index, JValue::Object(services))
+            },
+            ["service", name] => {
+                let tag = query.get("tag");
+                let mut instances = Vec::new();
+                for (n, node) in &self.nodes {
+                    for service in node.services.values() {
+                        if service.name != *name || tag.is_some_and(|t| !service.tags.contains(t)) {
+                            continue;
+                        }
+                        instances.push(json!({
+                            "ID": "",
+                            "Node": n,
+                            "Address": node.address,
+                            "Datacenter": DATACENTER,
+                            "TaggedAddresses": node.tagged_addresses,
+                            "NodeMeta": node.meta,
+                            "ServiceID": service.id,
+                            "ServiceName": service.name,
+                            "ServiceAddress": service.address,
+                            "ServiceTags": service.tags,
+                            "ServicePort": service.port,
+                            "ServiceEnableTagOverride": service.enable_tag_override,
+                            "CreateIndex": service.create_index,
+                            "ModifyIndex": service.modify_index,
+                        }));
+                    }
+                }
+                Reply::json(index, JValue::Array(instances))
+            },
+            ["node", name] => match self.nodes.get(*name) {
+                Some(node) => {
+                    let services = node.services.iter().map(|(id, s)| (id.clone(), _service_json(s))).collect();
+                    Reply::json(index, json!({ "Node": _node_json(name, node), "Services": JValue::Object(services) }))
+                },
+                None => Reply::json(index, JValue::Null),
+            },
+            ["register"] => {
+                let req: JValue = serde_json::from_slice(body).unwrap_or(JValue::Null);
+                let name = _str(&req, "Node");
+                if name.is_empty() {
+                    return Reply::error(index, StatusCode::BadRequest, "Must provide node");
+                }
+                let index = self.index + 1;
+                let node = self.nodes.entry(name.clone()).or_insert_with(|| Node::new("", index));
+                node.address = _str(&req, "Address");
+                node.modify_index = index;
+                if req["TaggedAddresses"].is_object() {
+                    node.tagged_addresses = req["TaggedAddresses"].clone();
+                }
+                if req["NodeMeta"].is_object() {
+                    node.meta = req["NodeMeta"].clone();
+                }
+                let mut res = Ok(());
+                if !req["Service"].is_null() {
+                    res = self.register_service(index, &name, &req["Service"]).map(|_| ());
+                }
+                if res.is_ok() && !req["Check"].is_null() {
+                    res = self.register_check(index, &name, &req["Check"], "");
+                }
+                match res {
+                    Ok(()) => {
+                        self.index = index;
+                        Reply::json(index, json!(true))
+                    },
+                    Err(err) => Reply::error(self.index, StatusCode::InternalServerError, &err),
+                }
+            },
+            ["deregister"] => {
+                let req: JValue = serde_json::from_slice(body).unwrap_or(JValue::Null);
+                let name = _str(&req, "Node");
+                let index = self.index + 1;
+                match (req["ServiceID"].as_str(), req["CheckID"].as_str()) {
+                    (Some(service_id), _) => if let Some(node) = self.nodes.get_mut(&name) {
+                        node.services.remove(service_id);
+                        node.checks = node.checks.iter().filter(|&(_, c)| c.service_id != service_id).map(|(k, c)| (k.clone(), c.clone())).collect();
+                    },
+                    (None, Some(check_id)) => if let Some(node) = self.nodes.get_mut(&name) {
+                        node.checks.remove(check_id);
+                    },
+                    // The agent's anti-entropy registers its own node again right away
+                    (None, None) => if name != NODE {
+                        self.nodes.remove(&name);
+                    },
+                }
+                self.index = index;
+                Reply::json(index, json!(true))
+            },
+            _ => Reply::not_found(index),
+        }
+    }
+
+    // Health
+
+    fn health(&self, path: &[&str], query: &Query) -> Reply {
+        let index = self.index;
+        let checks = |filter: &dyn Fn(&Check) -> bool| {
+            let mut checks = Vec::new();
+            for (n, node) in &self.nodes {
+                checks.extend(node.checks.values().filter(|c| filter(c)).map(|c| _check_json(n, c)));
+            }
+            JValue::Array(checks)
+        };
+        match path {
+            ["node", name] => match self.nodes.get(*name) {
+                Some(node) => Reply::json(index, JValue::Array(node.checks.values().map(|c| _check_json(name, c)).collect())),
+                None => Reply::json(index, json!([])),
+            },
+            ["checks", service] => Reply::json(index, checks(&|c| c.service_name == *service)),
+            ["state", state] => Reply::json(index, checks(&|c| *state == "any" || c.status == *state)),
+            ["service", name] => {
+                let tag = query.get("tag");
+                let passing_only = query.contains_key("passing");
+                let mut entries = Vec::new();
+                for (n, node) in &self.nodes {
+                    for service in node.services.values() {
+                        if service.name != *name || tag.is_some_and(|t| !service.tags.contains(t)) {
+                            continue;
+                        }
+                        let service_checks = node.checks.values()
+                            .filter(|c| c.service_id.is_empty() || c.service_id == service.id)
+                            .collect::<Vec<_>>();
+                        if passing_only && service_checks.iter().any(|c| c.status != "passing") {
+                            continue;
+                        }
+                        entries.push(json!({
+                            "Node": _node_json(n, node),
+                            "Service": _service_json(service),
+                            "Checks": service_checks.iter().map(|c| _check_json(n, c)).collect::<Vec<_>>(),
+                        }));
+                    }
+                }
+                Reply::json(index, JValue::Array(entries))
+            },
+            _ => Reply::not_found(index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{CatalogDeregistration, FakeConsul};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn blocking_query_wakes_on_write() {
+        let fake = FakeConsul::new();
+        let consul = fake.consul();
+
+        assert!(consul.kv().insert("test/key", "v1").unwrap());
+        let (_, meta) = consul.kv().get_blocking("test/key", 0, None).unwrap();
+
+        let writer = fake.consul();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            assert!(writer.kv().insert("test/key", "v2").unwrap());
+        });
+
+        let start = Instant::now();
+        let (value, meta2) = consul.kv().get_blocking("test/key", meta.last_index, Some(Duration::from_secs(10))).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(value, Some("v2".to_string()));
+        assert!(meta2.last_index > meta.last_index);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn deregister_agent_node() {
+        let consul = FakeConsul::new().consul();
+        let node = consul.agent().self_info().unwrap().config.node_name;
+
+        assert!(consul.catalog().deregister(&CatalogDeregistration { node: node.clone(), .. Default::default() }).is_ok());
+        assert!(consul.agent().checks().is_ok());
+        assert!(consul.catalog().nodes(None).unwrap().iter().any(|n| n.node == node));
+        assert!(consul.kv().insert("test/key", "v1").unwrap());
+    }
+}
@@ -0,0 +1,187 @@
+use hyper;
+use hyper::Url;
+use hyper::method::Method::Get;
+
+use serde::Deserialize;
+use serde_json;
+
+use {AgentService, CatalogNode, CheckStatus, Consul};
+use error::{consul_error, Error};
+
+use std::io::Read;
+
+// IDs of the checks Consul adds to nodes and services put in maintenance mode
+const NODE_MAINTENANCE_CHECK: &str = "_node_maintenance";
+const SERVICE_MAINTENANCE_CHECK_PREFIX: &str = "_service_maintenance:";
+
+// HealthCheck represents a check as seen by the cluster
+#[derive(Deserialize, Debug, Clone)]
+pub struct HealthCheck {
+    #[serde(rename = "Node")]
+    pub node: String,
+    #[serde(rename = "CheckID")]
+    pub check_id: String,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Status")]
+    pub status: CheckStatus,
+    #[serde(rename = "Notes")]
+    pub notes: String,
+    #[serde(rename = "Output")]
+    pub output: String,
+    #[serde(rename = "ServiceID")]
+    pub service_id: String,
+    #[serde(rename = "ServiceName")]
+    pub service_name: String,
+    #[serde(default, rename = "CreateIndex")]
+    pub create_index: u64,
+    #[serde(default, rename = "ModifyIndex")]
+    pub modify_index: u64,
+}
+
+impl HealthCheck {
+    /// Whether this check is the one marking its node or service in maintenance mode
+    pub fn is_maintenance(&self) -> bool {
+        self.check_id == NODE_MAINTENANCE_CHECK || self.check_id.starts_with(SERVICE_MAINTENANCE_CHECK_PREFIX)
+    }
+}
+
+// ServiceEntry is an instance of a service together with its node and all the checks that apply to it
+#[derive(Deserialize, Debug, Clone)]
+pub struct ServiceEntry {
+    #[serde(rename = "Node")]
+    pub node: CatalogNode,
+    #[serde(rename = "Service")]
+    pub service: AgentService,
+    #[serde(rename = "Checks")]
+    pub checks: Vec<HealthCheck>,
+}
+
+impl ServiceEntry {
+    /// The worst status among the instance's checks, or maintenance if its node or the service is in maintenance mode
+    pub fn aggregated_status(&self) -> CheckStatus {
+        if self.checks.iter().any(HealthCheck::is_maintenance) {
+            return CheckStatus::Maintenance;
+        }
+        CheckStatus::aggregate(self.checks.iter().map(|c| &c.status))
+    }
+}
+
+pub struct Health<'a> {
+    consul: &'a Consul
+}
+
+impl<'a> Health<'a> {
+    pub fn new(consul: &'a Consul) -> Self {
+        Health { consul: consul }
+    }
+
+    /// Returns the checks of a node
+    pub fn node(&self, node: &str) -> ::Result<Vec<HealthCheck>> {
+        self._get("health/node", node, |_| ())
+    }
+
+    /// Returns the checks associated with a service
+    pub fn checks(&self, service: &str) -> ::Result<Vec<HealthCheck>> {
+        self._get("health/checks", service, |_| ())
+    }
+
+    /// Lists the instances of a service with their nodes and checks,
+    /// optionally only those with `tag` and only those with all checks passing
+    pub fn service(&self, service: &str, tag: Option<&str>, passing_only: bool) -> ::Result<Vec<ServiceEntry>> {
+        self._get("health/service", service, |u| {
+            if let Some(tag) = tag { u.query_pairs_mut().append_pair("tag", tag); }
+            if passing_only { u.query_pairs_mut().append_pair("passing", "true"); }
+        })
+    }
+
+    /// Returns the checks in the given state, or all checks if `state` is `None`.
+    /// Only passing, warning and critical can be queried.
+    pub fn state(&self, state: Option<CheckStatus>) -> ::Result<Vec<HealthCheck>> {
+        match state {
+            None => self._get("health/state", "any", |_| ()),
+            Some(ref state) if state._is_check_state() => self._get("health/state", state.as_str(), |_| ()),
+            Some(state) => Err(Error::InvalidCheckStatus(state)),
+        }
+    }
+
+    fn _get<T, F>(&self, srv: &str, segment: &str, url_f: F) -> ::Result<Vec<T>> where T: Deserialize, F: Fn(&mut Url) {
+        let mut res = self.consul._request3(Get, srv, &[segment], url_f).send()?;
+        match res.status {
+            hyper::Ok => {
+                let mut buf = String::new();
+                res.read_to_string(&mut buf)?;
+
+                // Consul returns `null` instead of an empty list
+                let v: Option<Vec<T>> = serde_json::from_str(&buf)?;
+                Ok(v.unwrap_or_default())
+            },
+            _ => Err(consul_error(res)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use ::{AgentCheckRegistration, AgentServiceRegistration, CheckStatus, FakeConsul, ServiceEntry};
+    use std::time::Duration;
+
+    #[test]
+    fn service_health() {
+        let consul = FakeConsul::new().consul();
+
+        assert!(consul.agent().register_service(&AgentServiceRegistration {
+            name: "test_health_service".into(),
+            tags: Some(vec!["primary".into()]),
+            check: Some(AgentCheckRegistration {
+                name: "test_health_check".into(),
+                ttl: Some(Duration::from_secs(15)),
+                status: Some(CheckStatus::Critical),
+                .. Default::default()
+            }),
+            .. Default::default()
+        }).is_ok());
+
+        let entries = consul.health().service("test_health_service", Some("primary"), false).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].service.service, "test_health_service");
+        assert!(entries[0].checks.iter().any(|c| c.service_id == "test_health_service" && c.status == CheckStatus::Critical));
+        assert_eq!(entries[0].aggregated_status(), CheckStatus::Critical);
+        assert!(consul.health().service("test_health_service", None, true).unwrap().is_empty());
+
+        let checks = consul.health().checks("test_health_service").unwrap();
+        assert_eq!(checks.len(), 1);
+        assert!(consul.health().node(&entries[0].node.node).unwrap().iter().any(|c| c.check_id == checks[0].check_id));
+        assert!(consul.health().state(Some(CheckStatus::Critical)).unwrap().iter().any(|c| c.check_id == checks[0].check_id));
+
+        assert!(consul.agent().pass_service_check("test_health_service", None).is_ok());
+        assert_eq!(consul.health().service("test_health_service", None, true).unwrap().len(), 1);
+        assert!(consul.health().state(Some(CheckStatus::Passing)).unwrap().iter().any(|c| c.check_id == checks[0].check_id));
+        assert!(consul.health().state(None).unwrap().iter().any(|c| c.check_id == checks[0].check_id));
+        assert_eq!(consul.health().service("test_health_service", None, false).unwrap()[0].aggregated_status(), CheckStatus::Passing);
+
+        assert!(consul.health().state(Some(CheckStatus::Maintenance)).is_err());
+
+        assert!(consul.agent().deregister_service("test_health_service").is_ok());
+        assert!(consul.health().service("test_health_service", None, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn maintenance_status() {
+        let check = |id: &str, status: &str| json!({
+            "Node": "node1", "CheckID": id, "Name": id, "Status": status, "Notes": "", "Output": "",
+            "ServiceID": "", "ServiceName": "",
+        });
+        let entry = |checks: Vec<_>| serde_json::from_value::<ServiceEntry>(json!({
+            "Node": { "Node": "node1", "Address": "127.0.0.1" },
+            "Service": { "ID": "web", "Service": "web", "Tags": [], "Port": 80, "Address": "", "EnableTagOverride": false },
+            "Checks": checks,
+        })).unwrap();
+
+        assert_eq!(entry(vec![check("serfHealth", "passing"), check("web_check", "warning")]).aggregated_status(), CheckStatus::Warning);
+        assert_eq!(entry(vec![check("serfHealth", "passing"), check("_node_maintenance", "critical")]).aggregated_status(), CheckStatus::Maintenance);
+        assert_eq!(entry(vec![check("_service_maintenance:web", "critical")]).aggregated_status(), CheckStatus::Maintenance);
+        assert_eq!(entry(vec![check("web_check", "unhealthy")]).checks[0].status, CheckStatus::Unknown("unhealthy".into()));
+    }
+}
@@ -0,0 +1,204 @@
+use {CheckStatus, Consul};
+use error::Error;
+
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Keeps a zero or tiny TTL from turning the heartbeat into a busy loop against the agent
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct TtlHeartbeatOptions {
+    /// How many times per TTL the check is updated, so that a few failed updates don't let it expire
+    pub updates_per_ttl: u32,
+    /// Deregister the check when the heartbeat is dropped, instead of marking it critical
+    pub deregister_on_drop: bool,
+}
+
+impl Default for TtlHeartbeatOptions {
+    fn default() -> Self {
+        TtlHeartbeatOptions {
+            updates_per_ttl: 3,
+            deregister_on_drop: false,
+        }
+    }
+}
+
+enum Signal {
+    Update,
+    Stop,
+}
+
+struct HeartbeatState {
+    status: CheckStatus,
+    note: Option<String>,
+    last_error: Option<String>,
+}
+
+// TtlHeartbeat keeps a TTL check alive by updating it from a background thread,
+// with the status and note set by the application (passing until told otherwise).
+// When dropped the check is marked critical, or deregistered, so it doesn't linger as passing until the TTL runs out.
+pub struct TtlHeartbeat {
+    state: Arc<Mutex<HeartbeatState>>,
+    signal: Sender<Signal>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TtlHeartbeat {
+    /// Starts updating the check registered with `ttl` under `check_id`
+    pub fn start(consul: &Consul, check_id: &str, ttl: Duration) -> Self {
+        Self::with_options(consul, check_id, ttl, Default::default())
+    }
+
+    pub fn with_options(consul: &Consul, check_id: &str, ttl: Duration, opts: TtlHeartbeatOptions) -> Self {
+        let consul = consul.clone();
+        let check_id = check_id.to_string();
+        let interval = cmp::max(ttl / opts.updates_per_ttl.max(1), MIN_UPDATE_INTERVAL);
+        let state = Arc::new(Mutex::new(HeartbeatState { status: CheckStatus::Passing, note: None, last_error: None }));
+        let (signal, signal_rx) = mpsc::channel();
+
+        let thread_state = state.clone();
+        let thread = thread::spawn(move || {
+            let agent = consul.agent();
+            loop {
+                let (status, note) = {
+                    let state = thread_state.lock().unwrap_or_else(|e| e.into_inner());
+                    (state.status.clone(), state.note.clone())
+                };
+                // A failed update isn't retried before the next tick: with a few updates per TTL
+                // the check only turns critical if Consul stays unreachable for most of the TTL
+                let res = agent.update_check(&check_id, status, note.as_deref());
+                thread_state.lock().unwrap_or_else(|e| e.into_inner()).last_error = res.err().map(|e| e.to_string());
+
+                match signal_rx.recv_timeout(interval) {
+                    Ok(Signal::Update) | Err(RecvTimeoutError::Timeout) => continue,
+                    Ok(Signal::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+
+            let _ = if opts.deregister_on_drop {
+                agent.deregister_check(&check_id)
+            } else {
+                agent.update_check(&check_id, CheckStatus::Critical, Some("Heartbeat stopped"))
+            };
+        });
+
+        TtlHeartbeat { state: state, signal: signal, thread: Some(thread) }
+    }
+
+    /// Changes the status reported from now on, e.g. to warning while a dependency is degraded.
+    /// The check is updated right away rather than on the next tick.
+    /// TTL checks can only be passing, warning or critical, other statuses are rejected.
+    pub fn set_status(&self, status: CheckStatus, note: Option<&str>) -> ::Result<()> {
+        if !status._is_check_state() {
+            return Err(Error::InvalidCheckStatus(status));
+        }
+        {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.status = status;
+            state.note = note.map(|n| n.to_string());
+        }
+        let _ = self.signal.send(Signal::Update);
+        Ok(())
+    }
+
+    pub fn status(&self) -> CheckStatus {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).status.clone()
+    }
+
+    /// Why the last update of the check failed, `None` once an update succeeds again
+    pub fn last_error(&self) -> Option<String> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).last_error.clone()
+    }
+
+    /// Stops the heartbeat and waits until the check has been marked critical or deregistered
+    pub fn stop(self) {}
+}
+
+impl Drop for TtlHeartbeat {
+    fn drop(&mut self) {
+        let _ = self.signal.send(Signal::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{AgentCheckRegistration, CheckStatus, FakeConsul, TtlHeartbeat, TtlHeartbeatOptions};
+    use fake::Partition;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn heartbeat() {
+        let fake = FakeConsul::new();
+        let consul = fake.consul();
+        let ttl = Duration::from_secs(15);
+        let status = |id| consul.agent().checks().unwrap().get(id).map(|c| (c.status.clone(), c.output.clone()));
+
+        for id in &["test_heartbeat", "test_heartbeat_dereg"] {
+            consul.agent().register_check(&AgentCheckRegistration {
+                name: id.to_string(),
+                ttl: Some(ttl),
+                .. Default::default()
+            }).unwrap();
+        }
+
+        let heartbeat = TtlHeartbeat::start(&consul, "test_heartbeat", ttl);
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(status("test_heartbeat"), Some((CheckStatus::Passing, "".to_string())));
+
+        heartbeat.set_status(CheckStatus::Warning, Some("pool degraded")).unwrap();
+        assert_eq!(heartbeat.status(), CheckStatus::Warning);
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(status("test_heartbeat"), Some((CheckStatus::Warning, "pool degraded".to_string())));
+        assert_eq!(heartbeat.last_error(), None);
+
+        assert!(heartbeat.set_status(CheckStatus::Maintenance, None).is_err());
+        assert_eq!(heartbeat.status(), CheckStatus::Warning);
+
+        heartbeat.stop();
+        assert_eq!(status("test_heartbeat").map(|s| s.0), Some(CheckStatus::Critical));
+
+        let opts = TtlHeartbeatOptions { deregister_on_drop: true, .. Default::default() };
+        drop(TtlHeartbeat::with_options(&consul, "test_heartbeat_dereg", ttl, opts));
+        assert_eq!(status("test_heartbeat_dereg"), None);
+
+        let partition = Partition::new(&fake);
+        let heartbeat = TtlHeartbeat::start(&partition.consul(), "test_heartbeat", ttl);
+        partition.set_down(true);
+        heartbeat.set_status(CheckStatus::Passing, None).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert!(heartbeat.last_error().is_some());
+
+        partition.set_down(false);
+        heartbeat.set_status(CheckStatus::Passing, None).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(heartbeat.last_error(), None);
+    }
+
+    #[test]
+    fn zero_ttl() {
+        let consul = FakeConsul::new().consul();
+        let node = consul.agent().self_info().unwrap().config.node_name;
+        let modify_index = || consul.health().node(&node).unwrap().iter()
+            .find(|c| c.check_id == "test_heartbeat_zero").map_or(0, |c| c.modify_index);
+        consul.agent().register_check(&AgentCheckRegistration {
+            name: "test_heartbeat_zero".into(),
+            ttl: Some(Duration::from_secs(15)),
+            .. Default::default()
+        }).unwrap();
+
+        let start = modify_index();
+        let heartbeat = TtlHeartbeat::start(&consul, "test_heartbeat_zero", Duration::new(0, 0));
+        thread::sleep(Duration::from_millis(350));
+        let updates = modify_index() - start;
+        heartbeat.stop();
+        assert!(updates > 0 && updates < 10, "updated {} times", updates);
+    }
+}
@@ -1,6 +1,6 @@
 use hyper;
 use hyper::method::Method::{Get, Put, Delete};
-//use hyper::header::ContentType;
+use hyper::header::ContentType;
 
 use base64;
 use serde;
@@ -8,44 +8,166 @@ use serde::de::{Deserialize, Deserializer};
 use serde_json;
 use ::JValue;
 
-use Consul;
+use {Consul, HttpResponse, QueryMeta};
+#[cfg(any(test, feature = "async"))]
+use {AsyncConsul, ConsulFuture, RequestBuilder};
 use error::consul_error;
 
 use std::io::Read;
+use std::time::Duration;
 
 pub struct KeyValue<'a> {
     consul: &'a Consul
 }
 
+// KVEntry represents a single key with its value and metadata
 #[derive(Clone, Debug, Deserialize)]
-struct KVEntry {
+pub struct KVEntry {
     #[serde(rename = "CreateIndex")]
-    create_index: u64,
+    pub create_index: u64,
     #[serde(rename = "ModifyIndex")]
-    modify_index: u64,
+    pub modify_index: u64,
     #[serde(rename = "LockIndex")]
-    lock_index: u64,
+    pub lock_index: u64,
     #[serde(rename = "Key")]
-    key: String,
+    pub key: String,
     #[serde(rename = "Flags")]
-    flags: u64,
+    pub flags: u64,
     #[serde(rename = "Value", deserialize_with = "_deserialize_base64")]
-    value: Vec<u8>,
+    pub value: Vec<u8>,
     #[serde(default, rename = "Session")]
-    session: Option<String>,
+    pub session: Option<String>,
 }
 
+// Consul sends `null` for empty values and for the results of write operations in a transaction
 fn _deserialize_base64<D>(deserializer: D) -> Result<Vec<u8>, D::Error> where D: Deserializer {
-    let s = try!(String::deserialize(deserializer));
-    base64::decode(&s).map_err(serde::de::Error::custom)
+    match try!(Option::<String>::deserialize(deserializer)) {
+        Some(s) => base64::decode(&s).map_err(serde::de::Error::custom),
+        None => Ok(Vec::new()),
+    }
 }
 
-/*
-pub struct KVTransaction<'a> {
-    consul: &'a Consul,
+// KVTransaction queues KV operations to be applied atomically by `commit`.
+// `C` is the client committing it, `AsyncConsul` for transactions begun from `AsyncKeyValue`.
+pub struct KVTransaction<'a, C: 'a = Consul> {
+    consul: &'a C,
     ops: Vec<JValue>,
 }
-*/
+
+// KVTxnError describes why an operation caused the transaction to roll back
+#[derive(Clone, Debug, Deserialize)]
+pub struct KVTxnError {
+    #[serde(rename = "OpIndex")]
+    pub op_index: usize,
+    #[serde(rename = "What")]
+    pub what: String,
+}
+
+// KVTxnResponse holds the entries returned by the operations of a committed transaction
+// (only operations that read or write a key produce a result),
+// or the errors of the operations that caused it to roll back
+#[derive(Clone, Debug)]
+pub struct KVTxnResponse {
+    pub results: Vec<KVEntry>,
+    pub errors: Vec<KVTxnError>,
+}
+
+impl KVTxnResponse {
+    pub fn is_committed(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+#[derive(Deserialize)]
+struct TxnResult {
+    #[serde(rename = "KV")]
+    kv: KVEntry,
+}
+
+#[derive(Deserialize)]
+struct TxnResponse {
+    #[serde(default, rename = "Results")]
+    results: Option<Vec<TxnResult>>,
+    #[serde(default, rename = "Errors")]
+    errors: Option<Vec<KVTxnError>>,
+}
+
+fn _entry_response(mut res: HttpResponse) -> ::Result<(Option<KVEntry>, QueryMeta)> {
+    let meta = QueryMeta::from_response(&res);
+    match res.status {
+        hyper::NotFound => Ok((None, meta)),
+        hyper::Ok => {
+            let mut buf = String::new();
+            res.read_to_string(&mut buf)?;
+
+            let mut entry: Vec<KVEntry> = serde_json::from_str(&buf)?;
+            Ok((entry.pop(), meta))
+        },
+        _ => Err(consul_error(res))
+    }
+}
+
+fn _entries_response(mut res: HttpResponse) -> ::Result<(Vec<KVEntry>, QueryMeta)> {
+    let meta = QueryMeta::from_response(&res);
+    match res.status {
+        hyper::NotFound => Ok((Vec::new(), meta)),
+        hyper::Ok => {
+            let mut buf = String::new();
+            res.read_to_string(&mut buf)?;
+
+            let entries: Vec<KVEntry> = serde_json::from_str(&buf)?;
+            Ok((entries, meta))
+        },
+        _ => Err(consul_error(res))
+    }
+}
+
+fn _keys_response(mut res: HttpResponse) -> ::Result<Vec<String>> {
+    match res.status {
+        hyper::Ok => {
+            let mut buf = String::new();
+            res.read_to_string(&mut buf)?;
+
+            Ok(serde_json::from_str(&buf)?)
+        },
+        _ => Err(consul_error(res)),
+    }
+}
+
+fn _contains_response(res: HttpResponse) -> ::Result<bool> {
+    match res.status {
+        hyper::NotFound => Ok(false),
+        hyper::Ok => Ok(true),
+        _ => Err(consul_error(res)),
+    }
+}
+
+fn _txn_response(mut res: HttpResponse) -> ::Result<KVTxnResponse> {
+    match res.status {
+        hyper::Ok | hyper::status::StatusCode::Conflict => {
+            let mut buf = String::new();
+            res.read_to_string(&mut buf)?;
+
+            let resp: TxnResponse = serde_json::from_str(&buf)?;
+            Ok(KVTxnResponse {
+                results: resp.results.unwrap_or_default().into_iter().map(|r| r.kv).collect(),
+                errors: resp.errors.unwrap_or_default(),
+            })
+        },
+        _ => Err(consul_error(res)),
+    }
+}
+
+fn _bool_response(mut res: HttpResponse) -> ::Result<bool> {
+    match res.status {
+        hyper::Ok => {
+            let mut buf = String::new();
+            res.read_to_string(&mut buf)?;
+            Ok(buf.trim() == "true")
+        },
+        _ => Err(consul_error(res)),
+    }
+}
 
 impl<'a> KeyValue<'a> {
     pub fn new(consul: &'a Consul) -> Self {
@@ -64,43 +186,69 @@ impl<'a> KeyValue<'a> {
         self.get_entry(key).map(|x| x.map(|kv| kv.value))
     }
 
-    fn get_entry(&self, key: &str) -> ::Result<Option<KVEntry>> {
-        let mut res = self.consul._request2(Get, "kv", key.split('/')).send()?;
-        match res.status {
-            hyper::NotFound => Ok(None),
-            hyper::Ok => {
-                let mut buf = String::new();
-                res.read_to_string(&mut buf).expect("Cannot fill the buffer");
-
-                let mut entry: Vec<KVEntry> = serde_json::from_str(&buf).expect("Cannot parse JSON");
-                assert!(entry.len() == 1);
-                Ok(Some(entry.pop().unwrap()))
-            },
-            _ => Err(consul_error(res))
+    /// Returns the key's entry including its indexes, flags and lock session
+    pub fn get_entry(&self, key: &str) -> ::Result<Option<KVEntry>> {
+        self.get_entry_blocking(key, 0, None).map(|(entry, _)| entry)
+    }
+
+    /// Blocking query: waits up to `wait` until the key's modify index exceeds `index`,
+    /// then returns the value with the new query metadata.
+    pub fn get_blocking(&self, key: &str, index: u64, wait: Option<Duration>) -> ::Result<(Option<String>, QueryMeta)> {
+        let (entry, meta) = self.get_entry_blocking(key, index, wait)?;
+        match entry {
+            None => Ok((None, meta)),
+            Some(kv) => Ok((Some(String::from_utf8(kv.value)?), meta)),
         }
     }
 
+    pub fn get_bytes_blocking(&self, key: &str, index: u64, wait: Option<Duration>) -> ::Result<(Option<Vec<u8>>, QueryMeta)> {
+        self.get_entry_blocking(key, index, wait).map(|(entry, meta)| (entry.map(|kv| kv.value), meta))
+    }
+
+    /// Blocking variant of `get_entry`
+    pub fn get_entry_blocking(&self, key: &str, index: u64, wait: Option<Duration>) -> ::Result<(Option<KVEntry>, QueryMeta)> {
+        let res = self.consul._request3(Get, "kv", key.split('/'), |u| ::_blocking_query(u, index, wait)).send()?;
+        _entry_response(res)
+    }
+
+    /// Returns the entries of all keys starting with `prefix` in a single request
+    pub fn list_entries(&self, prefix: &str) -> ::Result<Vec<KVEntry>> {
+        self.list_entries_blocking(prefix, 0, None).map(|(entries, _)| entries)
+    }
+
+    /// Blocking variant of `list_entries`: waits up to `wait` until anything under `prefix` changes after `index`
+    pub fn list_entries_blocking(&self, prefix: &str, index: u64, wait: Option<Duration>) -> ::Result<(Vec<KVEntry>, QueryMeta)> {
+        let res = self.consul._request3(
+            Get, "kv", prefix.split('/'),
+            |u| { u.set_query(Some("recurse")); ::_blocking_query(u, index, wait); }
+        ).send()?;
+        _entries_response(res)
+    }
+
     pub fn contains_key(&self, key: &str) -> ::Result<bool> {
         let res = self.consul._request2(Get, "kv", key.split('/')).send()?;
-        match res.status {
-            hyper::NotFound => Ok(false),
-            hyper::Ok => Ok(true),
-            _ => Err(consul_error(res)),
-        }
+        _contains_response(res)
     }
 
     pub fn keys(&self, prefix: &str) -> ::Result<Vec<String>> {
-        let mut res = self.consul._request3(Get, "kv", prefix.split('/'), |u| u.set_query(Some("keys"))).send()?;
-        match res.status {
-            hyper::Ok => {
-                let mut buf = String::new();
-                res.read_to_string(&mut buf).expect("Cannot fill the buffer");
+        self._keys(prefix, None)
+    }
 
-                let v: JValue = serde_json::from_str(&buf).expect("Cannot parse JSON");
-                Ok(v.as_array().unwrap().into_iter().map(|x| x.as_str().unwrap().to_string()).collect::<Vec<_>>())
-            },
-            _ => Err(consul_error(res)),
-        }
+    /// Lists keys under `prefix` up to the next `separator`, like a directory listing:
+    /// deeper keys are collapsed into their common prefix (e.g. `"test/key2/"`)
+    pub fn keys_with_separator(&self, prefix: &str, separator: &str) -> ::Result<Vec<String>> {
+        self._keys(prefix, Some(separator))
+    }
+
+    fn _keys(&self, prefix: &str, separator: Option<&str>) -> ::Result<Vec<String>> {
+        let res = self.consul._request3(
+            Get, "kv", prefix.split('/'),
+            |u| {
+                u.set_query(Some("keys"));
+                if let Some(separator) = separator { u.query_pairs_mut().append_pair("separator", separator); }
+            }
+        ).send()?;
+        _keys_response(res)
     }
 
     pub fn insert(&self, key: &str, value: &str) -> ::Result<bool> {
@@ -108,17 +256,50 @@ impl<'a> KeyValue<'a> {
     }
 
     pub fn insert_bytes(&self, key: &str, value: &[u8]) -> ::Result<bool> {
-        let mut res = self.consul._request2(Put, "kv", key.split('/')).body(value).send()?;
-        match res.status {
-            hyper::Ok => {
-                let mut buf = String::new();
-                res.read_to_string(&mut buf).expect("Cannot fill the buffer");
-                Ok(buf.trim_right() == "true")
-            },
-            _ => Err(consul_error(res)),
+        self._insert(key, value, &[])
+    }
+
+    /// Check-and-set: writes the key only if its modify index is still `cas`
+    /// (0 means the key must not exist yet). Returns `false` if the index did not match.
+    pub fn insert_cas(&self, key: &str, value: &str, cas: u64) -> ::Result<bool> {
+        self.insert_bytes_cas(key, value.as_bytes(), cas)
+    }
+
+    pub fn insert_bytes_cas(&self, key: &str, value: &[u8], cas: u64) -> ::Result<bool> {
+        self._insert(key, value, &[("cas", cas.to_string())])
+    }
+
+    /// Writes the key together with opaque user `flags`, optionally as a check-and-set
+    pub fn insert_with_flags(&self, key: &str, value: &str, flags: u64, cas: Option<u64>) -> ::Result<bool> {
+        self.insert_bytes_with_flags(key, value.as_bytes(), flags, cas)
+    }
+
+    pub fn insert_bytes_with_flags(&self, key: &str, value: &[u8], flags: u64, cas: Option<u64>) -> ::Result<bool> {
+        match cas {
+            Some(cas) => self._insert(key, value, &[("flags", flags.to_string()), ("cas", cas.to_string())]),
+            None => self._insert(key, value, &[("flags", flags.to_string())]),
         }
     }
 
+    /// Writes the key and locks it by `session` if it's not already locked.
+    /// Returns `false` if the lock is held by another session.
+    pub fn acquire(&self, key: &str, value: &[u8], session: &str) -> ::Result<bool> {
+        self._insert(key, value, &[("acquire", session.to_string())])
+    }
+
+    /// Writes the key and releases the lock held by `session`
+    pub fn release(&self, key: &str, value: &[u8], session: &str) -> ::Result<bool> {
+        self._insert(key, value, &[("release", session.to_string())])
+    }
+
+    fn _insert(&self, key: &str, value: &[u8], params: &[(&str, String)]) -> ::Result<bool> {
+        let res = self.consul._request3(
+            Put, "kv", key.split('/'),
+            |u| if !params.is_empty() { u.query_pairs_mut().extend_pairs(params); }
+        ).body(value).send()?;
+        _bool_response(res)
+    }
+
     pub fn remove(&self, key: &str) -> ::Result<()> {
         let res = self.consul._request2(Delete, "kv", key.split('/')).send()?;
         match res.status {
@@ -127,6 +308,15 @@ impl<'a> KeyValue<'a> {
         }
     }
 
+    /// Deletes the key only if its modify index is still `cas`. Returns `false` if the index did not match.
+    pub fn remove_cas(&self, key: &str, cas: u64) -> ::Result<bool> {
+        let res = self.consul._request3(
+            Delete, "kv", key.split('/'),
+            |u| { u.query_pairs_mut().append_pair("cas", &cas.to_string()); }
+        ).send()?;
+        _bool_response(res)
+    }
+
     pub fn remove_tree(&self, prefix: &str) -> ::Result<()> {
         let res = self.consul._request3(Delete, "kv", prefix.split('/'), |u| u.set_query(Some("recurse"))).send()?;
         match res.status {
@@ -135,20 +325,272 @@ impl<'a> KeyValue<'a> {
         }
     }
 
-    /*
     pub fn begin_transaction(&self) -> KVTransaction {
         KVTransaction { consul: self.consul, ops: Vec::new() }
     }
-    */
+}
+
+impl<'a, C> KVTransaction<'a, C> {
+    fn push(&mut self, verb: &str, key: &str, value: Option<&[u8]>, index: Option<u64>, session: Option<&str>) -> &mut Self {
+        let mut op = json!({ "Verb": verb, "Key": key });
+        {
+            let op = op.as_object_mut().unwrap();
+            if let Some(value) = value {
+                op.insert("Value".to_string(), JValue::String(base64::encode(value)));
+            }
+            if let Some(index) = index {
+                op.insert("Index".to_string(), JValue::from(index));
+            }
+            if let Some(session) = session {
+                op.insert("Session".to_string(), JValue::String(session.to_string()));
+            }
+        }
+        self.ops.push(json!({ "KV": op }));
+        self
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8]) -> &mut Self {
+        self.push("set", key, Some(value), None, None)
+    }
+
+    /// Sets the key only if its modify index matches `index` (0 means the key must not exist)
+    pub fn cas(&mut self, key: &str, value: &[u8], index: u64) -> &mut Self {
+        self.push("cas", key, Some(value), Some(index), None)
+    }
+
+    pub fn lock(&mut self, key: &str, value: &[u8], session: &str) -> &mut Self {
+        self.push("lock", key, Some(value), None, Some(session))
+    }
+
+    pub fn unlock(&mut self, key: &str, value: &[u8], session: &str) -> &mut Self {
+        self.push("unlock", key, Some(value), None, Some(session))
+    }
+
+    /// Reads the key, failing the transaction if it does not exist
+    pub fn get(&mut self, key: &str) -> &mut Self {
+        self.push("get", key, None, None, None)
+    }
+
+    pub fn get_tree(&mut self, prefix: &str) -> &mut Self {
+        self.push("get-tree", prefix, None, None, None)
+    }
+
+    pub fn check_index(&mut self, key: &str, index: u64) -> &mut Self {
+        self.push("check-index", key, None, Some(index), None)
+    }
+
+    pub fn check_session(&mut self, key: &str, session: &str) -> &mut Self {
+        self.push("check-session", key, None, None, Some(session))
+    }
+
+    pub fn delete(&mut self, key: &str) -> &mut Self {
+        self.push("delete", key, None, None, None)
+    }
+
+    pub fn delete_tree(&mut self, prefix: &str) -> &mut Self {
+        self.push("delete-tree", prefix, None, None, None)
+    }
+
+    pub fn delete_cas(&mut self, key: &str, index: u64) -> &mut Self {
+        self.push("delete-cas", key, None, Some(index), None)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+impl<'a> KVTransaction<'a> {
+    /// Applies all queued operations atomically.
+    /// A rolled back transaction is not an `Err`: check `KVTxnResponse::is_committed`.
+    pub fn commit(&self) -> ::Result<KVTxnResponse> {
+        let res = self.consul._request1(Put, "txn")
+            .body(serde_json::to_string(&self.ops)?)
+            .header(ContentType::json())
+            .send()?;
+        _txn_response(res)
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+impl<'a> KVTransaction<'a, AsyncConsul> {
+    /// See `KVTransaction::commit`
+    pub fn commit(&self) -> ConsulFuture<KVTxnResponse> {
+        let body = match serde_json::to_string(&self.ops) {
+            Ok(body) => body,
+            Err(err) => return ::nonblocking::_ready(Err(err.into())),
+        };
+        let req = self.consul.consul()._request1(Put, "txn").body(body).header(ContentType::json());
+        self.consul._send(req, _txn_response)
+    }
+}
+
+// AsyncKeyValue is the async counterpart of `KeyValue`
+#[cfg(any(test, feature = "async"))]
+pub struct AsyncKeyValue<'a> {
+    consul: &'a AsyncConsul
+}
+
+#[cfg(any(test, feature = "async"))]
+impl<'a> AsyncKeyValue<'a> {
+    pub fn new(consul: &'a AsyncConsul) -> Self {
+        AsyncKeyValue { consul: consul }
+    }
+
+    pub fn get(&self, key: &str) -> ConsulFuture<Option<String>> {
+        self.consul._send(self._get(key, 0, None), |res| match _entry_response(res)?.0 {
+            None => Ok(None),
+            Some(kv) => Ok(Some(String::from_utf8(kv.value)?)),
+        })
+    }
+
+    pub fn get_bytes(&self, key: &str) -> ConsulFuture<Option<Vec<u8>>> {
+        self.consul._send(self._get(key, 0, None), |res| _entry_response(res).map(|(entry, _)| entry.map(|kv| kv.value)))
+    }
+
+    pub fn get_entry(&self, key: &str) -> ConsulFuture<Option<KVEntry>> {
+        self.consul._send(self._get(key, 0, None), |res| _entry_response(res).map(|(entry, _)| entry))
+    }
+
+    /// See `KeyValue::get_blocking`
+    pub fn get_blocking(&self, key: &str, index: u64, wait: Option<Duration>) -> ConsulFuture<(Option<String>, QueryMeta)> {
+        self.consul._send(self._get(key, index, wait), |res| match _entry_response(res)? {
+            (None, meta) => Ok((None, meta)),
+            (Some(kv), meta) => Ok((Some(String::from_utf8(kv.value)?), meta)),
+        })
+    }
+
+    pub fn get_bytes_blocking(&self, key: &str, index: u64, wait: Option<Duration>) -> ConsulFuture<(Option<Vec<u8>>, QueryMeta)> {
+        self.consul._send(self._get(key, index, wait), |res| _entry_response(res).map(|(entry, meta)| (entry.map(|kv| kv.value), meta)))
+    }
+
+    pub fn get_entry_blocking(&self, key: &str, index: u64, wait: Option<Duration>) -> ConsulFuture<(Option<KVEntry>, QueryMeta)> {
+        self.consul._send(self._get(key, index, wait), _entry_response)
+    }
+
+    fn _get(&self, key: &str, index: u64, wait: Option<Duration>) -> RequestBuilder {
+        self.consul.consul()._request3(Get, "kv", key.split('/'), |u| ::_blocking_query(u, index, wait))
+    }
+
+    pub fn list_entries(&self, prefix: &str) -> ConsulFuture<Vec<KVEntry>> {
+        let req = self.consul.consul()._request3(Get, "kv", prefix.split('/'), |u| u.set_query(Some("recurse")));
+        self.consul._send(req, |res| _entries_response(res).map(|(entries, _)| entries))
+    }
+
+    /// See `KeyValue::list_entries_blocking`
+    pub fn list_entries_blocking(&self, prefix: &str, index: u64, wait: Option<Duration>) -> ConsulFuture<(Vec<KVEntry>, QueryMeta)> {
+        let req = self.consul.consul()._request3(
+            Get, "kv", prefix.split('/'),
+            |u| { u.set_query(Some("recurse")); ::_blocking_query(u, index, wait); }
+        );
+        self.consul._send(req, _entries_response)
+    }
+
+    pub fn contains_key(&self, key: &str) -> ConsulFuture<bool> {
+        self.consul._send(self.consul.consul()._request2(Get, "kv", key.split('/')), _contains_response)
+    }
+
+    pub fn keys(&self, prefix: &str) -> ConsulFuture<Vec<String>> {
+        self._keys(prefix, None)
+    }
+
+    /// See `KeyValue::keys_with_separator`
+    pub fn keys_with_separator(&self, prefix: &str, separator: &str) -> ConsulFuture<Vec<String>> {
+        self._keys(prefix, Some(separator))
+    }
+
+    fn _keys(&self, prefix: &str, separator: Option<&str>) -> ConsulFuture<Vec<String>> {
+        let req = self.consul.consul()._request3(
+            Get, "kv", prefix.split('/'),
+            |u| {
+                u.set_query(Some("keys"));
+                if let Some(separator) = separator { u.query_pairs_mut().append_pair("separator", separator); }
+            }
+        );
+        self.consul._send(req, _keys_response)
+    }
+
+    pub fn insert(&self, key: &str, value: &str) -> ConsulFuture<bool> {
+        self.insert_bytes(key, value.as_bytes())
+    }
+
+    pub fn insert_bytes(&self, key: &str, value: &[u8]) -> ConsulFuture<bool> {
+        self._insert(key, value, &[])
+    }
+
+    /// See `KeyValue::insert_cas`
+    pub fn insert_cas(&self, key: &str, value: &str, cas: u64) -> ConsulFuture<bool> {
+        self.insert_bytes_cas(key, value.as_bytes(), cas)
+    }
+
+    pub fn insert_bytes_cas(&self, key: &str, value: &[u8], cas: u64) -> ConsulFuture<bool> {
+        self._insert(key, value, &[("cas", cas.to_string())])
+    }
+
+    /// See `KeyValue::insert_with_flags`
+    pub fn insert_with_flags(&self, key: &str, value: &str, flags: u64, cas: Option<u64>) -> ConsulFuture<bool> {
+        self.insert_bytes_with_flags(key, value.as_bytes(), flags, cas)
+    }
+
+    pub fn insert_bytes_with_flags(&self, key: &str, value: &[u8], flags: u64, cas: Option<u64>) -> ConsulFuture<bool> {
+        match cas {
+            Some(cas) => self._insert(key, value, &[("flags", flags.to_string()), ("cas", cas.to_string())]),
+            None => self._insert(key, value, &[("flags", flags.to_string())]),
+        }
+    }
+
+    /// See `KeyValue::acquire`
+    pub fn acquire(&self, key: &str, value: &[u8], session: &str) -> ConsulFuture<bool> {
+        self._insert(key, value, &[("acquire", session.to_string())])
+    }
+
+    pub fn release(&self, key: &str, value: &[u8], session: &str) -> ConsulFuture<bool> {
+        self._insert(key, value, &[("release", session.to_string())])
+    }
+
+    fn _insert(&self, key: &str, value: &[u8], params: &[(&str, String)]) -> ConsulFuture<bool> {
+        let req = self.consul.consul()._request3(
+            Put, "kv", key.split('/'),
+            |u| if !params.is_empty() { u.query_pairs_mut().extend_pairs(params); }
+        ).body(value);
+        self.consul._send(req, _bool_response)
+    }
+
+    pub fn remove(&self, key: &str) -> ConsulFuture<()> {
+        self.consul._send(self.consul.consul()._request2(Delete, "kv", key.split('/')), ::_empty_response)
+    }
+
+    /// See `KeyValue::remove_cas`
+    pub fn remove_cas(&self, key: &str, cas: u64) -> ConsulFuture<bool> {
+        let req = self.consul.consul()._request3(
+            Delete, "kv", key.split('/'),
+            |u| { u.query_pairs_mut().append_pair("cas", &cas.to_string()); }
+        );
+        self.consul._send(req, _bool_response)
+    }
+
+    pub fn remove_tree(&self, prefix: &str) -> ConsulFuture<()> {
+        let req = self.consul.consul()._request3(Delete, "kv", prefix.split('/'), |u| u.set_query(Some("recurse")));
+        self.consul._send(req, ::_empty_response)
+    }
+
+    pub fn begin_transaction(&self) -> KVTransaction<'a, AsyncConsul> {
+        KVTransaction { consul: self.consul, ops: Vec::new() }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use ::Consul;
+    use ::FakeConsul;
+    use std::time::Duration;
 
     #[test]
     fn base_ops() {
-        let consul = Consul::default();
+        let consul = FakeConsul::new().consul();
 
         assert!(consul.kv().insert("test/key0", "hello world").unwrap());
         assert!(consul.kv().insert("test/key1", "my • test • value").unwrap());
@@ -161,6 +603,7 @@ mod tests {
         assert_eq!(consul.kv().get("test/none").unwrap(), None);
 
         assert_eq!(consul.kv().keys("test/").unwrap(), vec!["test/key0".to_string(), "test/key1".to_string(), "test/key2/val".to_string()]);
+        assert_eq!(consul.kv().keys_with_separator("test/", "/").unwrap(), vec!["test/key0".to_string(), "test/key1".to_string(), "test/key2/".to_string()]);
 
         assert!(consul.kv().remove("test/key0").is_ok());
         assert_eq!(consul.kv().get("test/key0").unwrap(), None);
@@ -168,4 +611,100 @@ mod tests {
         assert!(consul.kv().remove_tree("test/").is_ok());
         assert!(consul.kv().keys("test/").is_err());
     }
+
+    #[test]
+    fn blocking_get() {
+        let consul = FakeConsul::new().consul();
+
+        assert!(consul.kv().insert("test_blocking/key", "v1").unwrap());
+        let (value, meta) = consul.kv().get_blocking("test_blocking/key", 0, None).unwrap();
+        assert_eq!(value, Some("v1".to_string()));
+        assert!(meta.last_index > 0);
+
+        // Nothing changes, so the query times out and returns the same index
+        let (_, meta2) = consul.kv().get_blocking("test_blocking/key", meta.last_index, Some(Duration::from_millis(100))).unwrap();
+        assert_eq!(meta2.last_index, meta.last_index);
+
+        assert!(consul.kv().insert("test_blocking/key", "v2").unwrap());
+        let (value, meta3) = consul.kv().get_blocking("test_blocking/key", meta.last_index, Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(value, Some("v2".to_string()));
+        assert!(meta3.last_index > meta.last_index);
+
+        assert!(consul.kv().remove_tree("test_blocking/").is_ok());
+    }
+
+    #[test]
+    fn entries() {
+        let consul = FakeConsul::new().consul();
+
+        assert!(consul.kv().insert_with_flags("test_entries/key0", "v0", 7, None).unwrap());
+        assert!(consul.kv().insert("test_entries/key1", "v1").unwrap());
+
+        let entry = consul.kv().get_entry("test_entries/key0").unwrap().unwrap();
+        assert_eq!(entry.key, "test_entries/key0");
+        assert_eq!(entry.flags, 7);
+        assert!(entry.create_index > 0 && entry.modify_index >= entry.create_index);
+        assert_eq!(entry.session, None);
+
+        let entries = consul.kv().list_entries("test_entries/").unwrap();
+        assert_eq!(entries.iter().map(|e| e.key.as_str()).collect::<Vec<_>>(), vec!["test_entries/key0", "test_entries/key1"]);
+
+        let (entries, meta) = consul.kv().list_entries_blocking("test_entries/", 0, None).unwrap();
+        assert_eq!(entries.len(), 2);
+        let (entries, _) = consul.kv().list_entries_blocking("test_entries/", meta.last_index, Some(Duration::from_millis(100))).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        assert!(consul.kv().remove_tree("test_entries/").is_ok());
+        assert!(consul.kv().list_entries("test_entries/").unwrap().is_empty());
+    }
+
+    #[test]
+    fn cas_and_flags() {
+        let consul = FakeConsul::new().consul();
+
+        assert!(consul.kv().insert_cas("test_cas/key", "v1", 0).unwrap());
+        assert!(!consul.kv().insert_cas("test_cas/key", "v2", 0).unwrap());
+
+        let index = consul.kv().get_entry("test_cas/key").unwrap().unwrap().modify_index;
+        assert!(consul.kv().insert_bytes_with_flags("test_cas/key", b"v2", 42, Some(index)).unwrap());
+        assert!(!consul.kv().insert_bytes_cas("test_cas/key", b"v3", index).unwrap());
+
+        let entry = consul.kv().get_entry("test_cas/key").unwrap().unwrap();
+        assert_eq!(entry.value, b"v2".to_vec());
+        assert_eq!(entry.flags, 42);
+
+        assert!(!consul.kv().remove_cas("test_cas/key", index).unwrap());
+        assert!(consul.kv().remove_cas("test_cas/key", entry.modify_index).unwrap());
+        assert_eq!(consul.kv().get("test_cas/key").unwrap(), None);
+    }
+
+    #[test]
+    fn transaction() {
+        let consul = FakeConsul::new().consul();
+
+        let resp = consul.kv().begin_transaction()
+            .set("test_txn/key0", b"value0")
+            .cas("test_txn/key1", b"value1", 0)
+            .get("test_txn/key0")
+            .commit().unwrap();
+        assert!(resp.is_committed());
+        assert_eq!(resp.results.len(), 3);
+        assert_eq!(resp.results[2].value, b"value0".to_vec());
+
+        let index = resp.results[1].modify_index;
+        let resp = consul.kv().begin_transaction()
+            .set("test_txn/key0", b"changed")
+            .check_index("test_txn/key1", index + 1)
+            .commit().unwrap();
+        assert!(!resp.is_committed());
+        assert_eq!(resp.errors[0].op_index, 1);
+        assert_eq!(consul.kv().get("test_txn/key0").unwrap(), Some("value0".to_string()));
+
+        let resp = consul.kv().begin_transaction()
+            .delete_cas("test_txn/key1", index)
+            .delete_tree("test_txn/")
+            .commit().unwrap();
+        assert!(resp.is_committed());
+        assert_eq!(consul.kv().get("test_txn/key1").unwrap(), None);
+    }
 }
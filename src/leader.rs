@@ -0,0 +1,184 @@
+use {Consul, LockOptions, SessionBehavior, SessionRenewer, SessionRequest};
+use lock::{_acquire_key, _watch_key};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How long a campaign blocks on the key before checking whether the election was stopped
+const CAMPAIGN_TIMEOUT: Duration = Duration::from_secs(5);
+// How often the leader checks whether the election was stopped; losing leadership wakes it right away
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaderEvent {
+    Elected,
+    Lost,
+}
+
+// LeaderElection campaigns for a KV lock from a background thread, reporting `LeaderEvent`s
+// as leadership is gained and lost, and campaigning again after it's lost.
+// A candidate keeps one session while it campaigns and only writes to the key when it's free.
+// Leadership is lost as soon as the key changes hands or the session can't be renewed within its TTL.
+// Leadership is given up when the election is dropped.
+pub struct LeaderElection {
+    leader: Arc<AtomicBool>,
+    events: Receiver<LeaderEvent>,
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LeaderElection {
+    pub fn start(consul: &Consul, key: &str) -> Self {
+        Self::start_with_options(consul, key, Default::default())
+    }
+
+    pub fn start_with_options(consul: &Consul, key: &str, opts: LockOptions) -> Self {
+        let consul = consul.clone();
+        let key = key.to_string();
+        let leader = Arc::new(AtomicBool::new(false));
+        let (events_tx, events) = mpsc::channel();
+        let (stop, stop_rx) = mpsc::channel();
+
+        let thread_leader = leader.clone();
+        let thread = thread::spawn(move || {
+            let set_leader = |value: bool| {
+                thread_leader.store(value, Ordering::SeqCst);
+                let _ = events_tx.send(if value { LeaderEvent::Elected } else { LeaderEvent::Lost });
+            };
+            let stopped = || !matches!(stop_rx.try_recv(), Err(TryRecvError::Empty));
+            // Backs off when Consul is unreachable, returns whether the election was stopped meanwhile
+            let back_off = || !matches!(stop_rx.recv_timeout(opts.retry_interval), Err(RecvTimeoutError::Timeout));
+
+            loop {
+                let created = consul.session().create(&SessionRequest {
+                    name: Some(opts.session_name.clone()),
+                    ttl: Some(opts.session_ttl),
+                    lock_delay: opts.lock_delay,
+                    behavior: Some(SessionBehavior::Release),
+                    .. Default::default()
+                });
+                let session = match created {
+                    Ok(session) => session,
+                    Err(_) if back_off() => return,
+                    Err(_) => continue,
+                };
+                let renewer = SessionRenewer::start(&consul, &session, opts.session_ttl);
+
+                // Campaign with this session until it's lost, then start over with a new one
+                let stop = loop {
+                    if stopped() {
+                        break true;
+                    }
+                    if !renewer.is_alive() {
+                        break false;
+                    }
+
+                    let deadline = Some(Instant::now() + CAMPAIGN_TIMEOUT);
+                    match _acquire_key(&consul, &key, &opts.value, &session, opts.retry_interval, deadline) {
+                        Ok(true) => {},
+                        Ok(false) => continue,
+                        Err(_) if back_off() => break true,
+                        Err(_) => continue,
+                    }
+
+                    renewer.watch(_watch_key(&key, &session, opts.session_ttl));
+                    set_leader(true);
+                    let stop = loop {
+                        if renewer.wait_lost(STOP_CHECK_INTERVAL) {
+                            break false;
+                        }
+                        if stopped() {
+                            break true;
+                        }
+                    };
+                    if stop {
+                        let _ = consul.kv().release(&key, &opts.value, &session);
+                    }
+                    set_leader(false);
+                    break stop;
+                };
+
+                renewer.stop();
+                let _ = consul.session().destroy(&session);
+                if stop {
+                    return;
+                }
+            }
+        });
+
+        LeaderElection { leader: leader, events: events, stop: stop, thread: Some(thread) }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::SeqCst)
+    }
+
+    /// Leadership changes, in order
+    pub fn events(&self) -> &Receiver<LeaderEvent> {
+        &self.events
+    }
+
+    /// Gives up leadership (if held) and stops campaigning
+    pub fn stop(self) {}
+}
+
+impl Drop for LeaderElection {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{FakeConsul, LeaderElection, LeaderEvent, LockOptions};
+    use fake::Partition;
+    use std::time::Duration;
+
+    #[test]
+    fn election() {
+        let consul = FakeConsul::new().consul();
+
+        let election1 = LeaderElection::start(&consul, "test_leader/key");
+        assert_eq!(election1.events().recv_timeout(Duration::from_secs(2)), Ok(LeaderEvent::Elected));
+        assert!(election1.is_leader());
+
+        let election2 = LeaderElection::start(&consul, "test_leader/key");
+        assert!(election2.events().recv_timeout(Duration::from_secs(1)).is_err());
+        assert!(!election2.is_leader());
+
+        election1.stop();
+        assert_eq!(election2.events().recv_timeout(Duration::from_secs(10)), Ok(LeaderEvent::Elected));
+        assert!(election2.is_leader());
+
+        election2.stop();
+        assert!(consul.kv().remove_tree("test_leader/").is_ok());
+    }
+
+    #[test]
+    fn partitioned_leader() {
+        let fake = FakeConsul::new();
+        let partition = Partition::new(&fake);
+        let opts = LockOptions { session_ttl: Duration::from_secs(2), lock_delay: Some(Duration::new(0, 0)), .. Default::default() };
+
+        let election1 = LeaderElection::start_with_options(&partition.consul(), "test_leader/partitioned", opts.clone());
+        assert_eq!(election1.events().recv_timeout(Duration::from_secs(2)), Ok(LeaderEvent::Elected));
+        let election2 = LeaderElection::start_with_options(&fake.consul(), "test_leader/partitioned", opts);
+
+        // The leader can't renew its session: it steps down once the TTL runs out, and the other candidate takes over
+        partition.set_down(true);
+        assert_eq!(election1.events().recv_timeout(Duration::from_secs(3)), Ok(LeaderEvent::Lost));
+        assert!(!election1.is_leader());
+        assert_eq!(election2.events().recv_timeout(Duration::from_secs(10)), Ok(LeaderEvent::Elected));
+
+        election2.stop();
+        partition.set_down(false);
+        election1.stop();
+        assert!(fake.consul().kv().remove_tree("test_leader/").is_ok());
+    }
+}
@@ -1,32 +1,156 @@
-extern crate hyper;
+#[macro_use] extern crate hyper;
 extern crate serde;
-extern crate serde_json;
+#[macro_use] extern crate serde_json;
 #[macro_use] extern crate serde_derive;
 extern crate base64;
+extern crate hyper_native_tls;
+extern crate native_tls;
+extern crate url;
 
 pub mod agent;
+pub mod duration;
+pub mod catalog;
+pub mod config;
+pub mod health;
 pub mod keyvalue;
+pub mod session;
+pub mod lock;
+pub mod semaphore;
+pub mod leader;
+pub mod heartbeat;
 pub mod error;
+pub mod transport;
+#[cfg(unix)]
+pub mod unix;
+#[cfg(any(test, feature = "fake"))]
+pub mod fake;
+#[cfg(any(test, feature = "async"))]
+pub mod nonblocking;
 
-pub use agent::{Agent, AgentCheck, AgentCheckRegistration, AgentService, AgentServiceRegistration};
-pub use keyvalue::KeyValue;
+pub use agent::{Agent, AgentCheck, AgentCheckRegistration, AgentConfig, AgentMember, AgentSelf, AgentService, AgentServiceRegistration, CheckStatus, Coordinate, MemberStatus};
+pub use catalog::{Catalog, CatalogDeregistration, CatalogNode, CatalogNodeServices, CatalogRegistration, CatalogService};
+pub use config::ConsulBuilder;
+pub use duration::{format_duration, parse_duration};
+pub use health::{Health, HealthCheck, ServiceEntry};
+pub use keyvalue::{KeyValue, KVEntry, KVTransaction, KVTxnError, KVTxnResponse};
+pub use session::{Session, SessionBehavior, SessionEntry, SessionRenewer, SessionRequest};
+pub use lock::{Lock, LockOptions};
+pub use semaphore::{Semaphore, SemaphoreOptions};
+pub use leader::{LeaderElection, LeaderEvent};
+pub use heartbeat::{TtlHeartbeat, TtlHeartbeatOptions};
+
+pub use transport::{HttpRequest, HttpResponse, HyperTransport, RequestBuilder, Transport};
+#[cfg(any(test, feature = "fake"))]
+pub use fake::FakeConsul;
+#[cfg(any(test, feature = "async"))]
+pub use nonblocking::{AsyncConsul, AsyncTransport, ConsulFuture, ThreadTransport};
+#[cfg(any(test, feature = "async"))]
+pub use agent::AsyncAgent;
+#[cfg(any(test, feature = "async"))]
+pub use keyvalue::AsyncKeyValue;
 
 pub use error::Result;
 
 use hyper::Url;
-use hyper::client::RequestBuilder;
+use hyper::header::{Authorization, Basic};
 use hyper::method::Method;
 
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+
 pub use serde_json::Value as JValue;
 
+// QueryMeta holds the metadata Consul returns with every read query
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryMeta {
+    /// Value of `X-Consul-Index`, to be passed back as the index of the next blocking query
+    pub last_index: u64,
+    /// Value of `X-Consul-KnownLeader`
+    pub known_leader: bool,
+    /// Value of `X-Consul-LastContact`, the time since the server was last contacted by the leader
+    pub last_contact: Duration,
+}
+
+impl QueryMeta {
+    pub fn from_response(res: &HttpResponse) -> Self {
+        fn header<'r>(res: &'r HttpResponse, name: &str) -> Option<&'r str> {
+            res.headers.get_raw(name)
+                .and_then(|v| v.first())
+                .and_then(|v| str::from_utf8(v).ok())
+        }
+
+        QueryMeta {
+            last_index: header(res, "X-Consul-Index").and_then(|v| v.parse().ok()).unwrap_or(0),
+            known_leader: header(res, "X-Consul-KnownLeader") == Some("true"),
+            last_contact: Duration::from_millis(header(res, "X-Consul-LastContact").and_then(|v| v.parse().ok()).unwrap_or(0)),
+        }
+    }
+}
+
+/// Appends blocking query parameters (`index` and `wait`) to the url.
+/// An `index` of zero makes the query return immediately.
+fn _blocking_query(url: &mut Url, index: u64, wait: Option<Duration>) {
+    if index > 0 {
+        url.query_pairs_mut().append_pair("index", &index.to_string());
+    }
+    if let Some(wait) = wait {
+        url.query_pairs_mut().append_pair("wait", &format_duration(wait));
+    }
+}
+
+/// Maps an empty successful response to `()`
+fn _empty_response(res: HttpResponse) -> Result<()> {
+    match res.status {
+        hyper::Ok => Ok(()),
+        _ => Err(error::consul_error(res)),
+    }
+}
+
+header! { (XConsulToken, "X-Consul-Token") => [String] }
+
+// Cloning is cheap: the clones share the underlying transport
+#[derive(Clone)]
 pub struct Consul {
     address: String,
-    client: hyper::Client,
+    scheme: String,
+    token: Option<String>,
+    datacenter: Option<String>,
+    namespace: Option<String>,
+    http_auth: Option<Basic>,
+    transport: Arc<dyn Transport>,
 }
 
 impl Consul {
+    /// Creates a client for the agent at `address`: `host:port`, `https://host:port` or `unix:///path/to/socket`.
+    /// Panics if the address has an unsupported scheme, use `ConsulBuilder` to get an error instead.
     pub fn new<S>(address: S) -> Self where S: Into<String> {
-        Consul { address: address.into(), client: hyper::Client::new() }
+        ConsulBuilder::new().address(address).build().expect("Invalid Consul address")
+    }
+
+    /// See `ConsulBuilder` for the available options
+    pub fn builder() -> ConsulBuilder {
+        ConsulBuilder::new()
+    }
+
+    /// Configures the client from the `CONSUL_*` environment variables, see `ConsulBuilder::from_env`
+    pub fn from_env() -> Result<Self> {
+        ConsulBuilder::from_env().build()
+    }
+
+    /// Sets the ACL token sent with every request
+    pub fn set_token<S>(&mut self, token: S) where S: Into<String> {
+        self.token = Some(token.into());
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// Returns a client that sends `token` instead of the default one,
+    /// e.g. `consul.with_token(admin_token).kv().remove_tree("app/")`
+    pub fn with_token<S>(&self, token: S) -> Consul where S: Into<String> {
+        Consul { token: Some(token.into()), .. self.clone() }
     }
 
     pub fn default() -> Self {
@@ -37,10 +161,22 @@ impl Consul {
         Agent::new(self)
     }
 
+    pub fn catalog(&self) -> Catalog {
+        Catalog::new(self)
+    }
+
+    pub fn health(&self) -> Health {
+        Health::new(self)
+    }
+
     pub fn kv(&self) -> KeyValue {
         KeyValue::new(self)
     }
 
+    pub fn session(&self) -> Session {
+        Session::new(self)
+    }
+
     pub fn _request1(&self, method: Method, srv: &str) -> RequestBuilder {
         self._request3(method, srv, (None as Option<&str>).into_iter(), |_| ())
     }
@@ -51,17 +187,44 @@ impl Consul {
 
     pub fn _request3<I, F>(&self, method: Method, srv: &str, segments: I, url_f: F) -> RequestBuilder
         where I: IntoIterator, I::Item: AsRef<str>, F: Fn(&mut Url) -> () {
-        let mut url = Url::parse(&format!("http://{}/v1/{}", self.address, srv)).unwrap();
+        let mut url = Url::parse(&format!("{}://{}/v1/{}", self.scheme, self.address, srv)).expect("Address is checked by ConsulBuilder::build");
         url.path_segments_mut().unwrap().extend(segments);
         url_f(&mut url);
+        // Appended after `url_f`, which may replace the whole query
+        if let Some(ref dc) = self.datacenter {
+            url.query_pairs_mut().append_pair("dc", dc);
+        }
+        if let Some(ref ns) = self.namespace {
+            url.query_pairs_mut().append_pair("ns", ns);
+        }
 
-        self.client.request(method, url)
+        let mut req = RequestBuilder::new(&*self.transport, method, url);
+        if let Some(ref token) = self.token {
+            req = req.header(XConsulToken(token.clone()));
+        }
+        if let Some(ref auth) = self.http_auth {
+            req = req.header(Authorization(auth.clone()));
+        }
+        req
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use ::Consul;
+
     #[test]
     fn it_works() {
     }
+
+    #[test]
+    fn token_override() {
+        let mut consul = Consul::default();
+        assert_eq!(consul.token(), None);
+
+        consul.set_token("default-token");
+        assert_eq!(consul.token(), Some("default-token"));
+        assert_eq!(consul.with_token("other-token").token(), Some("other-token"));
+        assert_eq!(consul.token(), Some("default-token"));
+    }
 }
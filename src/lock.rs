@@ -0,0 +1,247 @@
+use {Consul, SessionBehavior, SessionRenewer, SessionRequest};
+
+use std::cmp;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct LockOptions {
+    pub session_name: String,
+    /// TTL of the session backing the lock; it's renewed at half of this interval
+    pub session_ttl: Duration,
+    pub lock_delay: Option<Duration>,
+    /// Value stored in the key while the lock is held
+    pub value: Vec<u8>,
+    /// How long to wait before retrying when the key is free but can't be acquired yet (lock delay)
+    pub retry_interval: Duration,
+}
+
+impl Default for LockOptions {
+    fn default() -> Self {
+        LockOptions {
+            session_name: "Consul API Lock".to_string(),
+            session_ttl: Duration::from_secs(15),
+            lock_delay: None,
+            value: Vec::new(),
+            retry_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+struct Holder {
+    session: String,
+    renewer: SessionRenewer,
+}
+
+// Lock is a distributed mutual exclusion lock on a KV key, backed by a session.
+// The session is renewed in the background while the lock is held; it's released on `unlock` or drop.
+pub struct Lock {
+    consul: Consul,
+    key: String,
+    opts: LockOptions,
+    holder: Option<Holder>,
+}
+
+impl Lock {
+    pub fn new(consul: &Consul, key: &str) -> Self {
+        Self::with_options(consul, key, Default::default())
+    }
+
+    pub fn with_options(consul: &Consul, key: &str, opts: LockOptions) -> Self {
+        Lock { consul: consul.clone(), key: key.to_string(), opts: opts, holder: None }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// ID of the session holding the lock, if acquired
+    pub fn session(&self) -> Option<&str> {
+        self.holder.as_ref().map(|h| h.session.as_str())
+    }
+
+    /// Blocks until the lock is acquired
+    pub fn lock(&mut self) -> ::Result<()> {
+        self._acquire(None).map(|_| ())
+    }
+
+    /// Tries to acquire the lock within `timeout`. Returns `false` if it's held by someone else.
+    pub fn try_lock(&mut self, timeout: Duration) -> ::Result<bool> {
+        self._acquire(Some(Instant::now() + timeout))
+    }
+
+    /// Returns `false` if the lock was never acquired or has been lost,
+    /// i.e. the session was invalidated or not renewed for a whole TTL, or the key is no longer locked by it
+    pub fn is_held(&self) -> bool {
+        match self.holder {
+            Some(ref holder) => holder.renewer.is_alive(),
+            None => false,
+        }
+    }
+
+    /// Releases the lock and destroys its session. Does nothing if the lock is not acquired.
+    pub fn unlock(&mut self) -> ::Result<()> {
+        match self.holder.take() {
+            None => Ok(()),
+            Some(holder) => {
+                // Releasing the key first ends the watch's blocking query, stopping the renewer joins it
+                let released = self.consul.kv().release(&self.key, &self.opts.value, &holder.session);
+                holder.renewer.stop();
+                let destroyed = self.consul.session().destroy(&holder.session);
+                released.and(destroyed)
+            },
+        }
+    }
+
+    fn _acquire(&mut self, deadline: Option<Instant>) -> ::Result<bool> {
+        if self.holder.is_some() {
+            return Ok(true);
+        }
+
+        let session = self.consul.session().create(&SessionRequest {
+            name: Some(self.opts.session_name.clone()),
+            ttl: Some(self.opts.session_ttl),
+            lock_delay: self.opts.lock_delay,
+            behavior: Some(SessionBehavior::Release),
+            .. Default::default()
+        })?;
+        // Waiting for the key can take longer than the TTL
+        let renewer = SessionRenewer::start(&self.consul, &session, self.opts.session_ttl);
+
+        match _acquire_key(&self.consul, &self.key, &self.opts.value, &session, self.opts.retry_interval, deadline) {
+            Ok(true) => {
+                renewer.watch(_watch_key(&self.key, &session, self.opts.session_ttl));
+                self.holder = Some(Holder { session: session, renewer: renewer });
+                Ok(true)
+            },
+            res => {
+                renewer.stop();
+                let _ = self.consul.session().destroy(&session);
+                res
+            },
+        }
+    }
+}
+
+/// Acquires `key` with `session`, waiting with blocking queries while someone else holds it.
+/// The key is only written to once it's seen free, so waiting contenders don't cost Raft writes.
+pub(crate) fn _acquire_key(consul: &Consul, key: &str, value: &[u8], session: &str, retry_interval: Duration, deadline: Option<Instant>) -> ::Result<bool> {
+    let remaining = || deadline.map(|deadline| deadline - cmp::min(deadline, Instant::now()));
+    let expired = || deadline.is_some() && remaining() == Some(Duration::new(0, 0));
+
+    let kv = consul.kv();
+    let mut index = 0;
+    loop {
+        let (entry, meta) = kv.get_entry_blocking(key, index, remaining())?;
+        let free = match entry.and_then(|e| e.session) {
+            Some(holder) => holder == session,
+            None => true,
+        };
+        if free {
+            if kv.acquire(key, value, session)? {
+                return Ok(true);
+            }
+            if expired() {
+                return Ok(false);
+            }
+            // The key is free but still protected by the lock delay
+            thread::sleep(remaining().map_or(retry_interval, |r| cmp::min(r, retry_interval)));
+            index = 0;
+        } else if expired() {
+            return Ok(false);
+        } else {
+            // Wait for the current holder to release the key
+            index = meta.last_index;
+        }
+    }
+}
+
+/// `SessionRenewer::watch` function reporting whether `key` is still locked by `session`
+pub(crate) fn _watch_key(key: &str, session: &str, wait: Duration) -> impl FnMut(&Consul, u64) -> ::Result<(bool, u64)> {
+    let (key, session) = (key.to_string(), session.to_string());
+    move |consul, index| {
+        let (entry, meta) = consul.kv().get_entry_blocking(&key, index, Some(wait))?;
+        Ok((entry.and_then(|e| e.session).as_ref() == Some(&session), meta.last_index))
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = self.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{FakeConsul, Lock, LockOptions};
+    use fake::Partition;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn lock_unlock() {
+        let consul = FakeConsul::new().consul();
+
+        let mut lock1 = Lock::with_options(&consul, "test_lock/key", LockOptions {
+            value: b"holder1".to_vec(),
+            .. Default::default()
+        });
+        let mut lock2 = Lock::new(&consul, "test_lock/key");
+
+        assert!(!lock1.is_held());
+        assert!(lock1.try_lock(Duration::from_secs(1)).unwrap());
+        assert!(lock1.is_held());
+
+        let entry = consul.kv().get_entry("test_lock/key").unwrap().unwrap();
+        assert_eq!(entry.value, b"holder1".to_vec());
+        assert_eq!(entry.session, lock1.session().map(|s| s.to_string()));
+
+        assert!(!lock2.try_lock(Duration::from_millis(500)).unwrap());
+        assert!(!lock2.is_held());
+
+        assert!(lock1.unlock().is_ok());
+        assert!(!lock1.is_held());
+        assert!(consul.kv().get_entry("test_lock/key").unwrap().unwrap().session.is_none());
+
+        // Released explicitly, so no lock delay applies
+        assert!(lock2.try_lock(Duration::from_secs(1)).unwrap());
+        drop(lock2);
+        assert!(consul.kv().get_entry("test_lock/key").unwrap().unwrap().session.is_none());
+
+        assert!(consul.kv().remove_tree("test_lock/").is_ok());
+    }
+
+    #[test]
+    fn lost_lock() {
+        let fake = FakeConsul::new();
+        let consul = fake.consul();
+        let opts = LockOptions { session_ttl: Duration::from_secs(2), lock_delay: Some(Duration::new(0, 0)), .. Default::default() };
+
+        // The key changing hands is noticed right away
+        let mut lock = Lock::with_options(&consul, "test_lock/lost", opts.clone());
+        assert!(lock.try_lock(Duration::from_secs(1)).unwrap());
+        consul.session().destroy(lock.session().unwrap()).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert!(!lock.is_held());
+        drop(lock);
+
+        // Consul can't be reached to renew the session: after a TTL it may have been invalidated
+        let partition = Partition::new(&fake);
+        let mut lock = Lock::with_options(&partition.consul(), "test_lock/lost", opts.clone());
+        assert!(lock.try_lock(Duration::from_secs(1)).unwrap());
+        partition.set_down(true);
+        thread::sleep(Duration::from_millis(1500));
+        assert!(lock.is_held());
+        thread::sleep(Duration::from_millis(1000));
+        assert!(!lock.is_held());
+
+        // Meanwhile the servers invalidated the session and someone else took the key
+        let mut other = Lock::with_options(&consul, "test_lock/lost", opts);
+        assert!(other.try_lock(Duration::from_secs(1)).unwrap());
+
+        drop(other);
+        partition.set_down(false);
+        drop(lock);
+        assert!(consul.kv().remove_tree("test_lock/").is_ok());
+    }
+}
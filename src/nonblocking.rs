@@ -0,0 +1,405 @@
+use {AsyncAgent, AsyncKeyValue, Consul, HttpRequest, HttpResponse, RequestBuilder, Transport};
+use error::Error;
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+/// Future returned by the async client, it can be awaited on any executor
+pub type ConsulFuture<T> = Pin<Box<dyn Future<Output = ::Result<T>> + Send>>;
+
+/// Sends requests to Consul without blocking the caller. `ThreadTransport` is used unless
+/// another one is given to `AsyncConsul::with_transport`, e.g. one built on an async http client.
+pub trait AsyncTransport: Send + Sync {
+    fn send(&self, request: HttpRequest) -> ConsulFuture<HttpResponse>;
+}
+
+// Number of request threads of `ThreadTransport::new`
+const DEFAULT_THREADS: usize = 16;
+
+// ThreadTransport sends requests through a blocking `Transport` on a bounded pool of threads,
+// so the executor is never blocked. A request is only sent once its future is first polled,
+// a future dropped before that sends nothing. Requests beyond the pool size wait for a free thread,
+// and a blocking query holds its thread until it returns: size the pool above the number of concurrent watches.
+pub struct ThreadTransport {
+    pool: Arc<Pool>,
+}
+
+impl ThreadTransport {
+    pub fn new<T>(transport: T) -> Self where T: Transport + 'static {
+        Self::with_threads(transport, DEFAULT_THREADS)
+    }
+
+    /// Sends at most `threads` requests at a time. Threads are started as needed, and exit once the transport is dropped.
+    pub fn with_threads<T>(transport: T, threads: usize) -> Self where T: Transport + 'static {
+        Self::_with_transport(Arc::new(transport), threads)
+    }
+
+    fn _with_transport(transport: Arc<dyn Transport>, threads: usize) -> Self {
+        let state = PoolState { jobs: VecDeque::new(), idle: 0, threads: 0, max_threads: cmp::max(threads, 1), closed: false };
+        ThreadTransport { pool: Arc::new(Pool { transport: transport, state: Mutex::new(state), available: Condvar::new() }) }
+    }
+}
+
+impl AsyncTransport for ThreadTransport {
+    fn send(&self, request: HttpRequest) -> ConsulFuture<HttpResponse> {
+        Box::pin(PoolRequest { pool: self.pool.clone(), request: Some(request), response: None })
+    }
+}
+
+impl Drop for ThreadTransport {
+    fn drop(&mut self) {
+        self.pool.state().closed = true;
+        self.pool.available.notify_all();
+    }
+}
+
+struct PoolState {
+    jobs: VecDeque<(HttpRequest, Sender)>,
+    idle: usize,
+    threads: usize,
+    max_threads: usize,
+    closed: bool,
+}
+
+struct Pool {
+    transport: Arc<dyn Transport>,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+impl Pool {
+    fn state(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn submit(self: &Arc<Self>, request: HttpRequest, response: Sender) {
+        let mut state = self.state();
+        state.jobs.push_back((request, response));
+        if state.jobs.len() <= state.idle || state.threads == state.max_threads {
+            drop(state);
+            self.available.notify_one();
+            return;
+        }
+
+        state.threads += 1;
+        drop(state);
+        let pool = self.clone();
+        let spawned = thread::Builder::new().name("consul-request".to_string()).spawn(move || pool.work());
+        if let Err(err) = spawned {
+            let mut state = self.state();
+            state.threads -= 1;
+            // Without any thread the queued requests would never complete
+            if state.threads == 0 {
+                for (_, response) in state.jobs.drain(..) {
+                    response.send(Err(Error::Io(io::Error::new(err.kind(), err.to_string()))));
+                }
+            }
+        }
+    }
+
+    fn work(&self) {
+        let mut state = self.state();
+        loop {
+            if let Some((request, response)) = state.jobs.pop_front() {
+                drop(state);
+                let res = panic::catch_unwind(AssertUnwindSafe(|| self.transport.send(request)));
+                response.send(res.unwrap_or_else(|_| Err(Error::Io(io::Error::other("The transport panicked")))));
+                state = self.state();
+            } else if state.closed {
+                state.threads -= 1;
+                return;
+            } else {
+                state.idle += 1;
+                state = self.available.wait(state).unwrap_or_else(|e| e.into_inner());
+                state.idle -= 1;
+            }
+        }
+    }
+}
+
+// PoolRequest hands its request to the pool when first polled, then waits for the response
+struct PoolRequest {
+    pool: Arc<Pool>,
+    request: Option<HttpRequest>,
+    response: Option<Receiver>,
+}
+
+impl Future for PoolRequest {
+    type Output = ::Result<HttpResponse>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Some(request) = this.request.take() {
+            let (tx, rx) = _oneshot();
+            this.response = Some(rx);
+            this.pool.submit(request, tx);
+        }
+        match this.response {
+            Some(ref mut response) => Pin::new(response).poll(cx),
+            None => unreachable!("the request is sent on the first poll"),
+        }
+    }
+}
+
+// AsyncConsul is the async counterpart of `Consul`, it shares its configuration and types.
+// Unless another transport is given, requests still run on blocking threads: each request in flight,
+// a blocking query included, takes one of the 16 threads of `ThreadTransport` and the rest wait for a free one.
+// Pass an `AsyncTransport` built on an async http client to `with_transport` to lift that limit.
+#[derive(Clone)]
+pub struct AsyncConsul {
+    consul: Consul,
+    transport: Arc<dyn AsyncTransport>,
+}
+
+impl AsyncConsul {
+    /// Sends the requests through the transport of `consul` on a `ThreadTransport` of the default size
+    pub fn new(consul: Consul) -> Self {
+        let transport = ThreadTransport::_with_transport(consul.transport.clone(), DEFAULT_THREADS);
+        Self::with_transport(consul, transport)
+    }
+
+    /// Takes the address, token, datacenter etc. from `consul` but sends the requests through `transport`
+    pub fn with_transport<T>(consul: Consul, transport: T) -> Self where T: AsyncTransport + 'static {
+        AsyncConsul { consul: consul, transport: Arc::new(transport) }
+    }
+
+    /// Returns the blocking client sharing this client's configuration
+    pub fn consul(&self) -> &Consul {
+        &self.consul
+    }
+
+    pub fn agent(&self) -> AsyncAgent {
+        AsyncAgent::new(self)
+    }
+
+    pub fn kv(&self) -> AsyncKeyValue {
+        AsyncKeyValue::new(self)
+    }
+
+    /// Sends the request prepared with `Consul::_request3` and friends, then hands the response to `f`
+    pub(crate) fn _send<T, F>(&self, req: RequestBuilder, f: F) -> ConsulFuture<T>
+        where T: 'static, F: FnOnce(HttpResponse) -> ::Result<T> + Send + 'static {
+        Box::pin(Map { inner: self.transport.send(req.into_request()), f: Some(f) })
+    }
+}
+
+// Map applies `f` to the response once the request completes
+struct Map<F> {
+    inner: ConsulFuture<HttpResponse>,
+    f: Option<F>,
+}
+
+// `f` is never pinned, it's only moved out by value
+impl<F> Unpin for Map<F> {}
+
+impl<T, F> Future for Map<F> where F: FnOnce(HttpResponse) -> ::Result<T> {
+    type Output = ::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.inner.as_mut().poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(res) => {
+                let f = this.f.take().expect("Map polled after completion");
+                Poll::Ready(res.and_then(f))
+            },
+        }
+    }
+}
+
+/// Returns a future that is already complete, for results known before sending anything
+pub(crate) fn _ready<T>(res: ::Result<T>) -> ConsulFuture<T> where T: Send + 'static {
+    Box::pin(Ready(Some(res)))
+}
+
+struct Ready<T>(Option<T>);
+
+impl<T> Unpin for Ready<T> {}
+
+impl<T> Future for Ready<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<T> {
+        Poll::Ready(self.get_mut().0.take().expect("Ready polled after completion"))
+    }
+}
+
+struct Slot {
+    value: Option<::Result<HttpResponse>>,
+    waker: Option<Waker>,
+}
+
+struct Sender {
+    slot: Arc<Mutex<Slot>>,
+}
+
+struct Receiver {
+    slot: Arc<Mutex<Slot>>,
+}
+
+fn _oneshot() -> (Sender, Receiver) {
+    let slot = Arc::new(Mutex::new(Slot { value: None, waker: None }));
+    (Sender { slot: slot.clone() }, Receiver { slot: slot })
+}
+
+impl Sender {
+    fn send(self, value: ::Result<HttpResponse>) {
+        let waker = {
+            let mut slot = self.slot.lock().unwrap_or_else(|e| e.into_inner());
+            slot.value = Some(value);
+            slot.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Future for Receiver {
+    type Output = ::Result<HttpResponse>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut slot = self.slot.lock().unwrap_or_else(|e| e.into_inner());
+        match slot.value.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+    use std::thread::{self, Thread};
+    use std::time::{Duration, Instant};
+
+    use ::{AgentCheckRegistration, AgentServiceRegistration, AsyncConsul, CheckStatus, FakeConsul, ThreadTransport};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F>(future: F) -> F::Output where F: Future {
+        let mut future = Box::pin(future);
+        let waker = Arc::new(ThreadWaker(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    // Polls the future once, which sends its request
+    fn start<F>(future: &mut Pin<Box<F>>) where F: Future + ?Sized {
+        let waker = Arc::new(ThreadWaker(thread::current())).into();
+        assert!(future.as_mut().poll(&mut Context::from_waker(&waker)).is_pending());
+    }
+
+    #[test]
+    fn requests_run_concurrently() {
+        let consul = AsyncConsul::new(FakeConsul::new().consul());
+        let kv = consul.kv();
+
+        block_on(kv.insert("test/key", "v1")).unwrap();
+        let (_, meta) = block_on(kv.get_blocking("test/key", 0, None)).unwrap();
+
+        // The blocking query is pending while the write completes
+        let mut watch = kv.get_blocking("test/key", meta.last_index, None);
+        start(&mut watch);
+        assert!(block_on(kv.insert("test/key", "v2")).unwrap());
+        let (value, _) = block_on(watch).unwrap();
+        assert_eq!(value, Some("v2".to_string()));
+    }
+
+    #[test]
+    fn requests_start_when_polled() {
+        let consul = AsyncConsul::new(FakeConsul::new().consul());
+
+        drop(consul.kv().insert("test/lazy", "v1"));
+        assert_eq!(consul.consul().kv().get("test/lazy").unwrap(), None);
+    }
+
+    #[test]
+    fn bounded_threads() {
+        let fake = FakeConsul::new();
+        let consul = AsyncConsul::with_transport(fake.consul(), ThreadTransport::with_threads(fake.clone(), 1));
+        let kv = consul.kv();
+
+        // The only thread is busy with the blocking query, the write waits for it
+        let (_, meta) = block_on(kv.get_blocking("test/key", 0, None)).unwrap();
+        let mut watch = kv.get_blocking("test/key", meta.last_index, Some(Duration::from_millis(500)));
+        start(&mut watch);
+        let started = Instant::now();
+        assert!(block_on(kv.insert("test/key", "v1")).unwrap());
+        assert!(started.elapsed() >= Duration::from_millis(400));
+        assert_eq!(block_on(watch).unwrap().0, None);
+    }
+
+    #[test]
+    fn key_value() {
+        let consul = AsyncConsul::new(FakeConsul::new().consul());
+        let kv = consul.kv();
+
+        assert!(block_on(kv.insert_with_flags("test/key0", "v0", 7, None)).unwrap());
+        let entry = block_on(kv.get_entry("test/key0")).unwrap().unwrap();
+        assert_eq!(entry.flags, 7);
+        assert!(!block_on(kv.insert_bytes_cas("test/key0", b"v1", entry.modify_index + 1)).unwrap());
+        assert!(block_on(kv.insert_bytes_cas("test/key0", b"v1", entry.modify_index)).unwrap());
+        let (value, _) = block_on(kv.get_bytes_blocking("test/key0", 0, None)).unwrap();
+        assert_eq!(value, Some(b"v1".to_vec()));
+
+        let resp = block_on(kv.begin_transaction().set("test/dir/key1", b"v1").get("test/key0").commit()).unwrap();
+        assert!(resp.is_committed());
+        assert_eq!(resp.results[1].value, b"v1".to_vec());
+        assert!(block_on(kv.contains_key("test/dir/key1")).unwrap());
+        assert_eq!(block_on(kv.keys_with_separator("test/", "/")).unwrap(), vec!["test/dir/".to_string(), "test/key0".to_string()]);
+
+        let index = block_on(kv.get_entry("test/key0")).unwrap().unwrap().modify_index;
+        assert!(block_on(kv.remove_cas("test/key0", index)).unwrap());
+        assert!(!block_on(kv.contains_key("test/key0")).unwrap());
+    }
+
+    #[test]
+    fn agent() {
+        let consul = AsyncConsul::new(FakeConsul::new().consul());
+        let agent = consul.agent();
+
+        let service = AgentServiceRegistration {
+            name: "test-async".to_string(),
+            check: Some(AgentCheckRegistration { ttl: Some(Duration::from_secs(15)), ..Default::default() }),
+            ..Default::default()
+        };
+        block_on(agent.register_service(&service)).unwrap();
+        assert!(block_on(agent.services()).unwrap().contains_key("test-async"));
+
+        block_on(agent.fail_service_check("test-async", None)).unwrap();
+        assert_eq!(block_on(agent.checks()).unwrap()["service:test-async"].status, CheckStatus::Critical);
+        block_on(agent.update_check("service:test-async", CheckStatus::Warning, Some("degraded"))).unwrap();
+        assert_eq!(block_on(agent.checks()).unwrap()["service:test-async"].output, "degraded");
+        assert!(block_on(agent.update_check("service:test-async", CheckStatus::Maintenance, None)).is_err());
+        block_on(agent.pass_service_check("test-async", None)).unwrap();
+        assert_eq!(block_on(agent.checks()).unwrap()["service:test-async"].status, CheckStatus::Passing);
+
+        block_on(agent.deregister_service("test-async")).unwrap();
+        assert!(!block_on(agent.services()).unwrap().contains_key("test-async"));
+    }
+}
@@ -0,0 +1,281 @@
+use serde_json;
+
+use {Consul, KVEntry, SessionBehavior, SessionRenewer, SessionRequest};
+use error::Error;
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct SemaphoreOptions {
+    pub session_name: String,
+    /// TTL of the session backing the slot; it's renewed at half of this interval
+    pub session_ttl: Duration,
+    /// Value stored in the contender key
+    pub value: Vec<u8>,
+}
+
+impl Default for SemaphoreOptions {
+    fn default() -> Self {
+        SemaphoreOptions {
+            session_name: "Consul API Semaphore".to_string(),
+            session_ttl: Duration::from_secs(15),
+            value: Vec::new(),
+        }
+    }
+}
+
+// Content of the `<prefix>/.lock` key, shared by all contenders and updated with check-and-set
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SemaphoreLock {
+    #[serde(rename = "Limit")]
+    limit: usize,
+    #[serde(rename = "Holders")]
+    holders: HashMap<String, bool>,
+}
+
+impl SemaphoreLock {
+    fn decode(entry: &KVEntry) -> ::Result<SemaphoreLock> {
+        Ok(serde_json::from_slice(&entry.value)?)
+    }
+}
+
+struct Holder {
+    session: String,
+    renewer: SessionRenewer,
+}
+
+// Semaphore lets up to `limit` holders across the cluster hold it at the same time.
+// Every contender locks its own `<prefix>/<session>` key, and the holders are tracked in `<prefix>/.lock`.
+pub struct Semaphore {
+    consul: Consul,
+    prefix: String,
+    limit: usize,
+    opts: SemaphoreOptions,
+    holder: Option<Holder>,
+}
+
+impl Semaphore {
+    /// Fails with `Error::ZeroSemaphoreLimit` if `limit` is zero
+    pub fn new(consul: &Consul, prefix: &str, limit: usize) -> ::Result<Self> {
+        Self::with_options(consul, prefix, limit, Default::default())
+    }
+
+    pub fn with_options(consul: &Consul, prefix: &str, limit: usize, opts: SemaphoreOptions) -> ::Result<Self> {
+        if limit == 0 {
+            return Err(Error::ZeroSemaphoreLimit);
+        }
+        Ok(Semaphore {
+            consul: consul.clone(),
+            prefix: prefix.trim_end_matches('/').to_string(),
+            limit: limit,
+            opts: opts,
+            holder: None,
+        })
+    }
+
+    /// ID of the session holding a slot, if acquired
+    pub fn session(&self) -> Option<&str> {
+        self.holder.as_ref().map(|h| h.session.as_str())
+    }
+
+    /// Blocks until a slot is acquired
+    pub fn acquire(&mut self) -> ::Result<()> {
+        self._acquire(None).map(|_| ())
+    }
+
+    /// Tries to acquire a slot within `timeout`. Returns `false` if all slots stay taken.
+    pub fn try_acquire(&mut self, timeout: Duration) -> ::Result<bool> {
+        self._acquire(Some(Instant::now() + timeout))
+    }
+
+    /// Returns `false` if no slot was acquired or it has been lost,
+    /// i.e. the session was invalidated or not renewed for a whole TTL, or the slot was taken away
+    pub fn is_held(&self) -> bool {
+        match self.holder {
+            Some(ref holder) => holder.renewer.is_alive(),
+            None => false,
+        }
+    }
+
+    /// Gives the slot back and destroys its session. Does nothing if no slot is acquired.
+    pub fn release(&mut self) -> ::Result<()> {
+        match self.holder.take() {
+            None => Ok(()),
+            Some(holder) => {
+                let removed = self._remove_holder(&holder.session);
+                holder.renewer.stop();
+                let deleted = self.consul.kv().remove(&self._contender_key(&holder.session));
+                let destroyed = self.consul.session().destroy(&holder.session);
+                removed.and(deleted).and(destroyed)
+            },
+        }
+    }
+
+    fn _lock_key(&self) -> String {
+        format!("{}/.lock", self.prefix)
+    }
+
+    fn _contender_key(&self, session: &str) -> String {
+        format!("{}/{}", self.prefix, session)
+    }
+
+    fn _acquire(&mut self, deadline: Option<Instant>) -> ::Result<bool> {
+        if self.holder.is_some() {
+            return Ok(true);
+        }
+
+        let session = self.consul.session().create(&SessionRequest {
+            name: Some(self.opts.session_name.clone()),
+            ttl: Some(self.opts.session_ttl),
+            behavior: Some(SessionBehavior::Delete),
+            .. Default::default()
+        })?;
+
+        // Waiting for a slot can take longer than the TTL
+        let renewer = SessionRenewer::start(&self.consul, &session, self.opts.session_ttl);
+
+        let contender_key = self._contender_key(&session);
+        let res = match self.consul.kv().acquire(&contender_key, &self.opts.value, &session) {
+            Ok(true) => self._acquire_with(&session, deadline),
+            Ok(false) => Err(Error::SemaphoreContenderLocked(contender_key.clone())),
+            Err(err) => Err(err),
+        };
+
+        match res {
+            Ok(true) => {
+                let (lock_key, id, wait) = (self._lock_key(), session.clone(), self.opts.session_ttl);
+                renewer.watch(move |consul, index| {
+                    let (entry, meta) = consul.kv().get_entry_blocking(&lock_key, index, Some(wait))?;
+                    let held = match entry {
+                        Some(entry) => SemaphoreLock::decode(&entry)?.holders.contains_key(&id),
+                        None => false,
+                    };
+                    Ok((held, meta.last_index))
+                });
+                self.holder = Some(Holder { session: session, renewer: renewer });
+                Ok(true)
+            },
+            res => {
+                renewer.stop();
+                let _ = self.consul.kv().remove(&contender_key);
+                let _ = self.consul.session().destroy(&session);
+                res
+            },
+        }
+    }
+
+    fn _acquire_with(&self, session: &str, deadline: Option<Instant>) -> ::Result<bool> {
+        let kv = self.consul.kv();
+        let lock_key = self._lock_key();
+        let mut index = 0;
+        // The slots are looked at once even with no time left
+        let mut first = true;
+        loop {
+            let remaining = match deadline {
+                None => None,
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if !first && now >= deadline {
+                        return Ok(false);
+                    }
+                    Some(deadline - ::std::cmp::min(deadline, now))
+                },
+            };
+
+            let (entries, meta) = kv.list_entries_blocking(&format!("{}/", self.prefix), index, remaining)?;
+            index = meta.last_index;
+            first = false;
+
+            let mut lock_entry = None;
+            let mut live = HashSet::new();
+            for entry in entries {
+                if entry.key == lock_key {
+                    lock_entry = Some(entry);
+                } else if let Some(session) = entry.session {
+                    live.insert(session);
+                }
+            }
+
+            let (mut lock, cas) = match lock_entry {
+                Some(entry) => (SemaphoreLock::decode(&entry)?, entry.modify_index),
+                None => (SemaphoreLock { limit: self.limit, holders: HashMap::new() }, 0),
+            };
+            if lock.limit != self.limit {
+                return Err(Error::SemaphoreLimitConflict(lock.limit, self.limit));
+            }
+
+            // Free the slots of contenders whose sessions are gone
+            lock.holders.retain(|holder, _| live.contains(holder));
+
+            if lock.holders.len() < self.limit {
+                lock.holders.insert(session.to_string(), true);
+                if kv.insert_bytes_cas(&lock_key, &serde_json::to_vec(&lock)?, cas)? {
+                    return Ok(true);
+                }
+                // Another contender updated the lock first, retry right away
+                index = 0;
+            }
+        }
+    }
+
+    fn _remove_holder(&self, session: &str) -> ::Result<()> {
+        let kv = self.consul.kv();
+        let lock_key = self._lock_key();
+        loop {
+            let entry = match kv.get_entry(&lock_key)? {
+                Some(entry) => entry,
+                None => return Ok(()),
+            };
+
+            let mut lock = SemaphoreLock::decode(&entry)?;
+            if lock.holders.remove(session).is_none() {
+                return Ok(());
+            }
+            if kv.insert_bytes_cas(&lock_key, &serde_json::to_vec(&lock)?, entry.modify_index)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Drop for Semaphore {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{FakeConsul, Semaphore};
+    use error::Error;
+    use std::time::Duration;
+
+    #[test]
+    fn acquire_release() {
+        let consul = FakeConsul::new().consul();
+
+        let mut sem1 = Semaphore::new(&consul, "test_semaphore", 2).unwrap();
+        let mut sem2 = Semaphore::new(&consul, "test_semaphore", 2).unwrap();
+        let mut sem3 = Semaphore::new(&consul, "test_semaphore", 2).unwrap();
+
+        assert!(sem1.try_acquire(Duration::from_secs(1)).unwrap());
+        assert!(sem2.try_acquire(Duration::from_secs(1)).unwrap());
+        assert!(sem1.is_held() && sem2.is_held());
+
+        assert!(!sem3.try_acquire(Duration::from_millis(500)).unwrap());
+        assert!(!sem3.is_held());
+
+        let conflict = Semaphore::new(&consul, "test_semaphore", 3).unwrap().try_acquire(Duration::from_millis(100));
+        assert!(matches!(conflict, Err(Error::SemaphoreLimitConflict(2, 3))));
+        assert!(matches!(Semaphore::new(&consul, "test_semaphore", 0), Err(Error::ZeroSemaphoreLimit)));
+
+        assert!(sem1.release().is_ok());
+        assert!(!sem1.is_held());
+        assert!(sem3.try_acquire(Duration::from_secs(1)).unwrap());
+
+        drop(sem2);
+        drop(sem3);
+        assert!(consul.kv().remove_tree("test_semaphore/").is_ok());
+    }
+}
@@ -0,0 +1,381 @@
+use hyper;
+use hyper::method::Method::{Get, Put};
+use hyper::header::ContentType;
+
+use serde_json;
+use ::JValue;
+
+use {Consul, HttpResponse};
+use error::consul_error;
+
+use std::io::Read;
+use std::cmp;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// SessionBehavior controls what happens to the locks held by a session when it is invalidated
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionBehavior {
+    #[serde(rename = "release")]
+    Release,
+    #[serde(rename = "delete")]
+    Delete,
+}
+
+// SessionEntry represents a session known to the cluster
+#[derive(Deserialize, Debug, Clone)]
+pub struct SessionEntry {
+    #[serde(rename = "CreateIndex")]
+    pub create_index: u64,
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Node")]
+    pub node: String,
+    #[serde(default, rename = "Checks")]
+    pub checks: Vec<String>,
+    #[serde(rename = "LockDelay", deserialize_with = "::duration::_deserialize_nanos")]
+    pub lock_delay: Duration,
+    #[serde(rename = "Behavior")]
+    pub behavior: SessionBehavior,
+    /// `None` for sessions that don't expire
+    #[serde(default, rename = "TTL", deserialize_with = "::duration::_deserialize_option")]
+    pub ttl: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SessionRequest {
+    #[serde(rename = "Name", skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "Node", skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    /// Health checks the session is tied to; `serfHealth` is used if unset
+    #[serde(rename = "Checks", skip_serializing_if = "Option::is_none")]
+    pub checks: Option<Vec<String>>,
+    #[serde(default, rename = "LockDelay", skip_serializing_if = "Option::is_none",
+            serialize_with = "::duration::_serialize_option", deserialize_with = "::duration::_deserialize_option")]
+    pub lock_delay: Option<Duration>,
+    #[serde(rename = "Behavior", skip_serializing_if = "Option::is_none")]
+    pub behavior: Option<SessionBehavior>,
+    #[serde(default, rename = "TTL", skip_serializing_if = "Option::is_none",
+            serialize_with = "::duration::_serialize_option", deserialize_with = "::duration::_deserialize_option")]
+    pub ttl: Option<Duration>,
+}
+
+impl SessionRequest {
+    pub fn from_json(x: JValue) -> ::Result<Self> {
+        Ok(serde_json::from_value(x)?)
+    }
+}
+
+#[derive(Deserialize)]
+struct SessionCreated {
+    #[serde(rename = "ID")]
+    id: String,
+}
+
+pub struct Session<'a> {
+    consul: &'a Consul
+}
+
+impl<'a> Session<'a> {
+    pub fn new(consul: &'a Consul) -> Self {
+        Session { consul: consul }
+    }
+
+    /// Creates a new session and returns its ID
+    pub fn create(&self, session: &SessionRequest) -> ::Result<String> {
+        let mut res = self.consul._request1(Put, "session/create")
+            .body(serde_json::to_string(session)?)
+            .header(ContentType::json())
+            .send()?;
+        match res.status {
+            hyper::Ok => {
+                let mut buf = String::new();
+                res.read_to_string(&mut buf)?;
+
+                let created: SessionCreated = serde_json::from_str(&buf)?;
+                Ok(created.id)
+            },
+            _ => Err(consul_error(res)),
+        }
+    }
+
+    pub fn destroy(&self, id: &str) -> ::Result<()> {
+        let res = self.consul._request2(Put, "session/destroy", &[id]).send()?;
+        match res.status {
+            hyper::Ok => Ok(()),
+            _ => Err(consul_error(res)),
+        }
+    }
+
+    /// Resets the session TTL. Returns `None` if the session no longer exists.
+    pub fn renew(&self, id: &str) -> ::Result<Option<SessionEntry>> {
+        self._single(Put, "session/renew", id)
+    }
+
+    pub fn info(&self, id: &str) -> ::Result<Option<SessionEntry>> {
+        self._single(Get, "session/info", id)
+    }
+
+    pub fn list(&self) -> ::Result<Vec<SessionEntry>> {
+        self._list(self.consul._request1(Get, "session/list").send()?)
+    }
+
+    pub fn node(&self, node: &str) -> ::Result<Vec<SessionEntry>> {
+        self._list(self.consul._request2(Get, "session/node", &[node]).send()?)
+    }
+
+    fn _single(&self, method: hyper::method::Method, srv: &str, id: &str) -> ::Result<Option<SessionEntry>> {
+        let res = self.consul._request2(method, srv, &[id]).send()?;
+        match res.status {
+            hyper::NotFound => Ok(None),
+            _ => self._list(res).map(|mut v| v.pop()),
+        }
+    }
+
+    fn _list(&self, mut res: HttpResponse) -> ::Result<Vec<SessionEntry>> {
+        match res.status {
+            hyper::Ok => {
+                let mut buf = String::new();
+                res.read_to_string(&mut buf)?;
+
+                // Consul returns `null` instead of an empty list
+                let v: Option<Vec<SessionEntry>> = serde_json::from_str(&buf)?;
+                Ok(v.unwrap_or_default())
+            },
+            _ => Err(consul_error(res)),
+        }
+    }
+}
+
+// How soon a failed renewal is retried, as long as the TTL hasn't run out
+const RENEW_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+struct RenewerStatus {
+    lost: bool,
+    stopped: bool,
+    /// When the last successful renewal was sent; the session is valid for a TTL from then on
+    confirmed: Instant,
+}
+
+// Shared by a SessionRenewer and its background threads
+struct RenewerState {
+    ttl: Duration,
+    status: Mutex<RenewerStatus>,
+    changed: Condvar,
+}
+
+impl RenewerState {
+    fn status(&self) -> MutexGuard<'_, RenewerStatus> {
+        self.status.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_lost(&self) {
+        self.status().lost = true;
+        self.changed.notify_all();
+    }
+
+    // Sleeps for `timeout` unless the renewer is stopped meanwhile, returns whether it's stopped
+    fn wait_stopped(&self, timeout: Duration) -> bool {
+        let status = self.status();
+        let (status, _) = self.changed.wait_timeout_while(status, timeout, |s| !s.stopped)
+            .unwrap_or_else(|e| e.into_inner());
+        status.stopped
+    }
+}
+
+// SessionRenewer keeps a session alive by renewing it from a background thread until stopped or dropped.
+// The session counts as lost once it's invalidated, a watch reports it lost, or no renewal has
+// succeeded for a whole TTL (e.g. during a network partition): by then the servers may have invalidated it.
+pub struct SessionRenewer {
+    consul: Consul,
+    state: Arc<RenewerState>,
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+    watches: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl SessionRenewer {
+    /// Renews the session every half of `ttl`, which must be the TTL it was created with.
+    /// Start it right after creating the session, its TTL runs from then.
+    pub fn start(consul: &Consul, id: &str, ttl: Duration) -> Self {
+        let state = Arc::new(RenewerState {
+            ttl: ttl,
+            status: Mutex::new(RenewerStatus { lost: false, stopped: false, confirmed: Instant::now() }),
+            changed: Condvar::new(),
+        });
+        let (stop, stop_rx) = mpsc::channel();
+
+        let (thread_consul, thread_state, id) = (consul.clone(), state.clone(), id.to_string());
+        let thread = thread::spawn(move || {
+            let mut interval = ttl / 2;
+            while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                let sent = Instant::now();
+                match thread_consul.session().renew(&id) {
+                    Ok(Some(_)) => {
+                        thread_state.status().confirmed = sent;
+                        interval = ttl / 2;
+                    },
+                    Ok(None) => return thread_state.set_lost(),
+                    Err(_) => {
+                        if thread_state.status().confirmed.elapsed() >= ttl {
+                            return thread_state.set_lost();
+                        }
+                        interval = cmp::min(RENEW_RETRY_INTERVAL, ttl / 2);
+                    },
+                }
+            }
+        });
+
+        SessionRenewer { consul: consul.clone(), state: state, stop: stop, thread: Some(thread), watches: Mutex::new(Vec::new()) }
+    }
+
+    /// Watches what the session protects from another thread until the session is lost or the renewer stopped.
+    /// `watch` is called in a loop with the index it last returned (0 at first) and should run a blocking query
+    /// from that index, returning whether the session still holds e.g. its lock, and the new index.
+    /// The thread is joined when the renewer stops, so release what the session holds first:
+    /// that ends the blocking query in progress.
+    pub fn watch<F>(&self, mut watch: F) where F: FnMut(&Consul, u64) -> ::Result<(bool, u64)> + Send + 'static {
+        let (consul, state) = (self.consul.clone(), self.state.clone());
+        let thread = thread::spawn(move || {
+            let mut index = 0;
+            loop {
+                {
+                    let status = state.status();
+                    if status.lost || status.stopped {
+                        return;
+                    }
+                }
+                match watch(&consul, index) {
+                    Ok((true, new_index)) => index = new_index,
+                    Ok((false, _)) => return state.set_lost(),
+                    // The renewer declares the session lost if Consul stays unreachable for a TTL
+                    Err(_) => if state.wait_stopped(RENEW_RETRY_INTERVAL) {
+                        return;
+                    },
+                }
+            }
+        });
+        self.watches.lock().unwrap_or_else(|e| e.into_inner()).push(thread);
+    }
+
+    /// Returns `false` once the session has been invalidated, reported lost by a watch,
+    /// or not renewed for a whole TTL
+    pub fn is_alive(&self) -> bool {
+        let status = self.state.status();
+        !status.lost && status.confirmed.elapsed() < self.state.ttl
+    }
+
+    /// Blocks until the session is lost or `timeout` passes, returns whether it's lost
+    pub fn wait_lost(&self, timeout: Duration) -> bool {
+        let end = Instant::now() + timeout;
+        let mut status = self.state.status();
+        loop {
+            let now = Instant::now();
+            let expiry = status.confirmed + self.state.ttl;
+            if status.lost || now >= expiry {
+                status.lost = true;
+                return true;
+            }
+            if now >= end {
+                return false;
+            }
+            status = self.state.changed.wait_timeout(status, cmp::min(expiry, end) - now)
+                .unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    /// Stops renewing and waits for the background threads to exit
+    pub fn stop(self) {}
+}
+
+impl Drop for SessionRenewer {
+    fn drop(&mut self) {
+        self.state.status().stopped = true;
+        self.state.changed.notify_all();
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        for thread in self.watches.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{FakeConsul, SessionBehavior, SessionRenewer, SessionRequest};
+    use error::Error;
+    use std::io;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // Flags when the watch closure owning it is dropped, i.e. its thread has exited
+    struct Exited(Arc<AtomicBool>);
+
+    impl Drop for Exited {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn sessions() {
+        let consul = FakeConsul::new().consul();
+
+        let id = consul.session().create(&SessionRequest {
+            name: Some("test_session".into()),
+            ttl: Some(Duration::from_secs(15)),
+            behavior: Some(SessionBehavior::Delete),
+            lock_delay: Some(Duration::from_secs(1)),
+            .. Default::default()
+        }).unwrap();
+
+        let info = consul.session().info(&id).unwrap().unwrap();
+        assert_eq!(info.name, "test_session");
+        assert_eq!(info.behavior, SessionBehavior::Delete);
+        assert_eq!(info.lock_delay, Duration::from_secs(1));
+        assert_eq!(info.ttl, Some(Duration::from_secs(15)));
+
+        assert!(consul.session().list().unwrap().iter().any(|s| s.id == id));
+        assert!(consul.session().node(&info.node).unwrap().iter().any(|s| s.id == id));
+        assert_eq!(consul.session().renew(&id).unwrap().unwrap().id, id);
+
+        assert!(consul.session().destroy(&id).is_ok());
+        assert!(consul.session().info(&id).unwrap().is_none());
+        assert!(consul.session().renew(&id).unwrap().is_none());
+
+        let id = consul.session().create(&SessionRequest::default()).unwrap();
+        assert_eq!(consul.session().info(&id).unwrap().unwrap().ttl, None);
+    }
+
+    #[test]
+    fn renewer_joins_watches() {
+        let consul = FakeConsul::new().consul();
+        let ttl = Duration::from_secs(10);
+        let id = consul.session().create(&SessionRequest { ttl: Some(ttl), .. Default::default() }).unwrap();
+
+        let exited = Arc::new(AtomicBool::new(false));
+        let guard = Exited(exited.clone());
+        let renewer = SessionRenewer::start(&consul, &id, ttl);
+        renewer.watch(move |_, _| {
+            let _ = &guard;
+            Err(Error::Io(io::Error::other("unreachable")))
+        });
+        thread::sleep(Duration::from_millis(100));
+
+        // A failing watch waits for the next attempt, stopping interrupts that
+        let start = Instant::now();
+        renewer.stop();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(exited.load(Ordering::SeqCst));
+    }
+}
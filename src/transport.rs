@@ -0,0 +1,195 @@
+use hyper;
+use hyper::Url;
+use hyper::header::{Header, HeaderFormat, Headers};
+use hyper::method::Method;
+use hyper::status::StatusCode;
+
+use std::io::{self, Read};
+
+// HttpRequest is a request to the Consul HTTP API as handed to a `Transport`
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: Url,
+    pub headers: Headers,
+    pub body: Option<Vec<u8>>,
+}
+
+// HttpResponse is a response returned by a `Transport`, with the body already read
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: Headers,
+    body: io::Cursor<Vec<u8>>,
+}
+
+impl HttpResponse {
+    pub fn new(status: StatusCode, headers: Headers, body: Vec<u8>) -> Self {
+        HttpResponse { status: status, headers: headers, body: io::Cursor::new(body) }
+    }
+
+    pub fn body(&self) -> &[u8] {
+        self.body.get_ref()
+    }
+}
+
+impl Read for HttpResponse {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+/// Sends requests to Consul. `HyperTransport` is used unless another one is given to `ConsulBuilder::transport`,
+/// e.g. to use a different http stack, record the traffic or answer from an in-process fake.
+pub trait Transport: Send + Sync {
+    fn send(&self, request: HttpRequest) -> ::Result<HttpResponse>;
+}
+
+// HyperTransport sends requests with a `hyper::Client`
+pub struct HyperTransport {
+    client: hyper::Client,
+}
+
+impl HyperTransport {
+    pub fn new() -> Self {
+        Self::with_client(hyper::Client::new())
+    }
+
+    pub fn with_client(client: hyper::Client) -> Self {
+        HyperTransport { client: client }
+    }
+}
+
+impl Default for HyperTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for HyperTransport {
+    fn send(&self, request: HttpRequest) -> ::Result<HttpResponse> {
+        let mut req = self.client.request(request.method, request.url).headers(request.headers);
+        if let Some(ref body) = request.body {
+            req = req.body(&body[..]);
+        }
+        let mut res = req.send()?;
+
+        let mut body = Vec::new();
+        res.read_to_end(&mut body)?;
+        Ok(HttpResponse::new(res.status, res.headers.clone(), body))
+    }
+}
+
+// RequestBuilder prepares a request and sends it through the client's transport
+pub struct RequestBuilder<'a> {
+    transport: &'a dyn Transport,
+    request: HttpRequest,
+}
+
+impl<'a> RequestBuilder<'a> {
+    pub fn new(transport: &'a dyn Transport, method: Method, url: Url) -> Self {
+        RequestBuilder {
+            transport: transport,
+            request: HttpRequest { method: method, url: url, headers: Headers::new(), body: None },
+        }
+    }
+
+    pub fn header<H>(mut self, header: H) -> Self where H: Header + HeaderFormat {
+        self.request.headers.set(header);
+        self
+    }
+
+    pub fn body<B>(mut self, body: B) -> Self where B: AsRef<[u8]> {
+        self.request.body = Some(body.as_ref().to_vec());
+        self
+    }
+
+    pub fn send(self) -> ::Result<HttpResponse> {
+        self.transport.send(self.request)
+    }
+
+    /// Returns the prepared request instead of sending it
+    pub fn into_request(self) -> HttpRequest {
+        self.request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::Headers;
+    use hyper::status::StatusCode;
+    use ::{ConsulBuilder, HttpRequest, HttpResponse, Transport};
+    use error::Error;
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder {
+        requests: Arc<Mutex<Vec<HttpRequest>>>,
+    }
+
+    impl Transport for Recorder {
+        fn send(&self, request: HttpRequest) -> ::Result<HttpResponse> {
+            self.requests.lock().unwrap().push(request);
+            Ok(HttpResponse::new(StatusCode::Ok, Headers::new(), b"true".to_vec()))
+        }
+    }
+
+    #[test]
+    fn custom_transport() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let consul = ConsulBuilder::new()
+            .token("secret")
+            .transport(Recorder { requests: requests.clone() })
+            .build().unwrap();
+
+        assert!(consul.kv().insert("test/key", "value").unwrap());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].url.as_str(), "http://127.0.0.1:8500/v1/kv/test/key");
+        assert_eq!(requests[0].headers.get_raw("X-Consul-Token"), Some(&[b"secret".to_vec()][..]));
+        assert_eq!(requests[0].body, Some(b"value".to_vec()));
+    }
+
+    // Answers every request like a misbehaving proxy would
+    struct Proxy(StatusCode, &'static [u8]);
+
+    impl Transport for Proxy {
+        fn send(&self, _: HttpRequest) -> ::Result<HttpResponse> {
+            Ok(HttpResponse::new(self.0, Headers::new(), self.1.to_vec()))
+        }
+    }
+
+    #[test]
+    fn malformed_responses() {
+        let consul = ConsulBuilder::new().transport(Proxy(StatusCode::Ok, b"<html>Bad Gateway</html>")).build().unwrap();
+        assert!(matches!(consul.kv().get_entry("test/key"), Err(Error::Json(_))));
+        assert!(matches!(consul.kv().keys("test/"), Err(Error::Json(_))));
+        assert!(matches!(consul.agent().checks(), Err(Error::Json(_))));
+        assert!(matches!(consul.agent().self_j(), Err(Error::Json(_))));
+
+        let consul = ConsulBuilder::new().transport(Proxy(StatusCode::Unregistered(599), b"\xff")).build().unwrap();
+        match consul.agent().services() {
+            Err(err @ Error::ServerError(..)) => assert!(err.is_retryable() && err.status() == Some(StatusCode::Unregistered(599))),
+            _ => panic!("expected a server error"),
+        }
+    }
+
+    #[test]
+    fn status_errors() {
+        let agent_error = |status, body| {
+            let consul = ConsulBuilder::new().transport(Proxy(status, body)).build().unwrap();
+            let err = consul.agent().reload().unwrap_err();
+            assert_eq!(err.status(), Some(status));
+            err
+        };
+        assert!(matches!(agent_error(StatusCode::BadRequest, b"Bad request"), Error::BadRequest(_, ref body) if body == "Bad request"));
+        assert!(matches!(agent_error(StatusCode::Forbidden, b"Permission denied\n"), Error::PermissionDenied(_, ref body) if body == "Permission denied"));
+        assert!(matches!(agent_error(StatusCode::NotFound, b""), Error::NotFound(..)));
+        assert!(matches!(agent_error(StatusCode::TooManyRequests, b""), Error::RateLimited(..)));
+        assert!(matches!(agent_error(StatusCode::BadGateway, b""), Error::ServerError(..)));
+        assert!(matches!(agent_error(StatusCode::Conflict, b""), Error::Status(..)));
+
+        assert!(!agent_error(StatusCode::Forbidden, b"").is_retryable());
+        assert!(agent_error(StatusCode::TooManyRequests, b"").is_retryable());
+    }
+}
@@ -0,0 +1,60 @@
+use hyper;
+use hyper::net::{NetworkConnector, NetworkStream};
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// UnixSocketConnector connects every request to the same unix domain socket, whatever the url's host
+pub struct UnixSocketConnector {
+    path: PathBuf,
+}
+
+impl UnixSocketConnector {
+    pub fn new<P>(path: P) -> Self where P: Into<PathBuf> {
+        UnixSocketConnector { path: path.into() }
+    }
+}
+
+impl NetworkConnector for UnixSocketConnector {
+    type Stream = UnixSocketStream;
+
+    fn connect(&self, _host: &str, _port: u16, _scheme: &str) -> hyper::Result<UnixSocketStream> {
+        Ok(UnixSocketStream(UnixStream::connect(&self.path)?))
+    }
+}
+
+pub struct UnixSocketStream(UnixStream);
+
+impl NetworkStream for UnixSocketStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        // Unix sockets have no ip address, hyper only uses it for logging
+        Ok(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)))
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.0.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.0.set_write_timeout(dur)
+    }
+}
+
+impl Read for UnixSocketStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for UnixSocketStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}